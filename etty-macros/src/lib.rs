@@ -32,6 +32,7 @@ struct CsiParse {
 
 impl syn::parse::Parse for CsiParse {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(syn::Attribute::parse_outer)?;
         let visi = input.parse::<syn::Visibility>()?;
         let nm = input.parse::<syn::Ident>()?;
         let _fat_arrow = input.parse::<Token![=>]>()?;
@@ -91,6 +92,7 @@ impl syn::parse::Parse for CsiParse {
             };
             quote! {
                 #[doc = #doc]
+                #(#attrs)*
                 #visi fn #nm (#(#arg_exprs,)*) ->  Csi<'static> {
                     #ret
                 }
@@ -169,8 +171,7 @@ impl syn::parse::Parse for CsiFmtParse {
                                         "expect arg name inside the `{}`",
                                     ));
                                 } else {
-                                    let bytes = nmbuf.drain(..).collect::<Vec<u8>>();
-                                    String::from_utf8(bytes).unwrap()
+                                    String::from_utf8(std::mem::take(&mut nmbuf)).unwrap()
                                 };
                                 nms_ord.push(nm);
                                 break 'cb;
@@ -476,6 +477,67 @@ pub fn outf(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     parse_macro_input!(input as Outf).0.into()
 }
 
+/// Same with [`etty::macros::out!`](etty::macros::out!) but evaluates to
+/// [`etty::Result<()>`](etty::Result) instead of panicking, e.g. on a closed pipe.
+///
+/// ```rust
+/// fn draw() -> etty::Result<()> {
+///     etty::try_out!("{}{}", etty::cus_home(), "hello")?;
+///     etty::try_outf!(etty::ers_ln_aft_cus())
+/// }
+/// ```
+#[proc_macro]
+pub fn try_out(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    struct TryOut(proc_macro2::TokenStream);
+    impl syn::parse::Parse for TryOut {
+        fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+            let fmtargs = input.parse::<FmtArgsExprs>()?.0;
+            let tts = quote! {{
+                use std::io::Write;
+                std::write!(std::io::stdout(), #fmtargs).map_err(etty::Error::from)
+            }};
+            Ok(TryOut(tts))
+        }
+    }
+    parse_macro_input!(input as TryOut).0.into()
+}
+
+/// Same with [`etty::macros::outln!`](etty::macros::outln!) but evaluates to [`etty::Result<()>`](etty::Result).
+#[proc_macro]
+pub fn try_outln(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    struct TryOutln(proc_macro2::TokenStream);
+    impl syn::parse::Parse for TryOutln {
+        fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+            let fmtargs = input.parse::<FmtArgsExprs>()?.0;
+            let tts = quote! {{
+                use std::io::Write;
+                std::writeln!(std::io::stdout(), #fmtargs).map_err(etty::Error::from)
+            }};
+            Ok(TryOutln(tts))
+        }
+    }
+    parse_macro_input!(input as TryOutln).0.into()
+}
+
+/// Same with [`etty::macros::outf!`](etty::macros::outf!) but evaluates to [`etty::Result<()>`](etty::Result).
+#[proc_macro]
+pub fn try_outf(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    struct TryOutf(proc_macro2::TokenStream);
+    impl syn::parse::Parse for TryOutf {
+        fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+            let fmtargs = input.parse::<FmtArgsExprs>()?.0;
+            let tts = quote! {{
+                use std::io::Write;
+                std::write!(std::io::stdout(), #fmtargs)
+                    .and_then(|()| std::io::stdout().flush())
+                    .map_err(etty::Error::from)
+            }};
+            Ok(TryOutf(tts))
+        }
+    }
+    parse_macro_input!(input as TryOutf).0.into()
+}
+
 struct FmtArgsExprs(proc_macro2::TokenStream);
 
 impl syn::parse::Parse for FmtArgsExprs {
@@ -2,11 +2,14 @@
 //!  
 //! ```rust
 //! assert_eq!(etty::ers_all().to_string(), "\x1b[2J");
-//! assert_eq!(etty::ers_char(3).to_string(), "\x1b[3J");
+//! assert_eq!(etty::ers_char(3).to_string(), "\x1b[3X");
 //! assert_eq!(etty::cus_goto(5, 15).to_string(), "\x1b[15;5H");
 //! assert_eq!(etty::sty_blink_rst().to_string(), "\x1b[25m");
 //! assert_eq!(etty::fg_rgb(42, 99, 123).to_string(), "\x1b[38;2;42;99;123m");
+//! assert_eq!(etty::sty_underln_curly_set().to_string(), "\x1b[4:3m");
+//! assert_eq!(etty::underln_clr_rgb(255, 0, 0).to_string(), "\x1b[58;2;255;0;0m");
 //! assert_eq!(etty::evt_mouse_set().to_string(), "\x1b[?1000h");
+//! assert_eq!(etty::evt_kbd_enh_push(etty::KBD_ENH_DISAMBIGUATE).to_string(), "\x1b[>1u");
 //! ```
 //!
 //! To learn more about ANSI CSI:
@@ -20,17 +23,14 @@
 #![allow(clippy::explicit_write)]
 
 use std::borrow::Cow;
-use std::io::Read;
 use std::io::Write;
 
-use crate::input;
-
 /// Representation CSI sequence.
 ///
 /// `Csi` provides convenience methods for writing into [`std::io::Stdout`](std::io::Stdout).
 /// It just a string wrapper, [`std::borrow::Cow<str>`](std::borrow::Cow) specifically.
 /// Created by functions in [`etty::csi`](etty::csi) module.
-pub struct Csi<'a>(Cow<'a, str>);
+pub struct Csi<'a>(#[doc(hidden)] pub Cow<'a, str>);
 
 impl<'a> std::fmt::Display for Csi<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -41,52 +41,34 @@ impl<'a> std::fmt::Display for Csi<'a> {
 impl<'a> Csi<'a> {
     /// Writes into [`std::io::Stdout`](std::io::Stdout).
     pub fn out(&self) {
-        std::write!(std::io::stdout(), "{}", self).unwrap();
+        self.try_out().unwrap();
     }
     /// Same with `Csi::out` but with newline.
     pub fn outln(&self) {
-        std::writeln!(std::io::stdout(), "{}", self).unwrap();
+        self.try_outln().unwrap();
     }
     /// Same with `Csi::out` but perform [`std::io::Stdout::flush`](std::io::Stdout::flush) immediately.
     pub fn outf(&self) {
-        self.out();
-        std::io::stdout().flush().unwrap();
+        self.try_outf().unwrap();
+    }
+    /// Same with `Csi::out` but returns the error instead of panicking, e.g. on a closed pipe.
+    pub fn try_out(&self) -> crate::Result<()> {
+        std::write!(std::io::stdout(), "{}", self)?;
+        crate::guard::track(&self.0);
+        Ok(())
+    }
+    /// Same with `Csi::outln` but returns the error instead of panicking.
+    pub fn try_outln(&self) -> crate::Result<()> {
+        std::writeln!(std::io::stdout(), "{}", self)?;
+        crate::guard::track(&self.0);
+        Ok(())
+    }
+    /// Same with `Csi::outf` but returns the error instead of panicking.
+    pub fn try_outf(&self) -> crate::Result<()> {
+        self.try_out()?;
+        std::io::stdout().flush()?;
+        Ok(())
     }
-}
-
-pub fn read_cus_pos() -> (u16, u16) {
-    let (mut stdin, jh) = {
-        let (stdin, jh) = input::async_stdin()
-            .timeout(std::time::Duration::from_millis(100))
-            .until(|b| b.map(|&b| b == b'R').unwrap_or(false))
-            .init();
-        (stdin.bytes(), jh)
-    };
-    let mut next = || stdin.next().unwrap().unwrap();
-
-    cus_pos_rpt().out();
-    std::io::stdout().flush().unwrap();
-
-    while next() != b'\x1b' {}
-    assert_eq!(next(), b'[');
-    let mut f = |til: u8| -> u16 {
-        let mut buf = [0_u8; 4];
-        let mut i = 0;
-        loop {
-            match next() {
-                b if b == til => break,
-                b @ b'0'..=b'9' => {
-                    buf[i] = b;
-                    i += 1;
-                }
-                _ => unreachable!(),
-            }
-        }
-        crate::bytes_to_uint::<u16>(&buf[0..i]).unwrap()
-    };
-    jh.join().unwrap();
-    let (y, x) = (f(b';'), f(b'R'));
-    (x, y)
 }
 
 // cursor
@@ -114,6 +96,15 @@ etty_macros::gen_csi! {
     pub cus_hide => "?25l";
 }
 
+// reports, see `etty::query` for reading the replies
+etty_macros::gen_csi! {
+    // mod rpt;
+    pub dev_attr_rpt => "c";
+    pub dev_attr2_rpt => ">c";
+    pub term_ver_rpt => ">0q";
+    pub mode_rpt => "?{mode}$p", mode;
+}
+
 // // c0
 // etty_macros::gen_csi! {
 //     mod c0;
@@ -206,6 +197,11 @@ etty_macros::gen_csi! {
     pub sty_underln_set => "4m";
     pub sty_underln_rst => "24m";
 
+    pub sty_underln_dbl_set => "4:2m";
+    pub sty_underln_curly_set => "4:3m";
+    pub sty_underln_dot_set => "4:4m";
+    pub sty_underln_dash_set => "4:5m";
+
     pub sty_blink_set => "5m";
     pub sty_blink_rst => "25m";
 
@@ -218,9 +214,13 @@ etty_macros::gen_csi! {
     pub sty_strkthru_set => "9m";
     pub sty_strkthru_rst => "29m";
 
+    pub sty_overln_set => "53m";
+    pub sty_overln_rst => "55m";
+
     // color default
     pub fg_rst => "39m";
     pub bg_rst => "49m";
+    pub underln_clr_rst => "59m";
 
     // color
     pub fg_blk => "30m";
@@ -259,10 +259,24 @@ etty_macros::gen_csi! {
     pub bg_brgt_wht => "107m";
 
     // color extended
+    ///
+    /// Not [downgraded](crate::style::color_depth), unlike [`Color::fg`](crate::style::Color::fg).
     pub fg_256color => "38;5;{val}m", val:u8;
+    ///
+    /// Not [downgraded](crate::style::color_depth), unlike [`Color::bg`](crate::style::Color::bg).
     pub bg_256color => "48;5;{val}m", val:u8;
+    ///
+    /// Not [downgraded](crate::style::color_depth), unlike [`Color::fg`](crate::style::Color::fg).
     pub fg_rgb => "38;2;{r};{g};{b}m", r:u8, g:u8, b:u8;
+    ///
+    /// Not [downgraded](crate::style::color_depth), unlike [`Color::bg`](crate::style::Color::bg).
     pub bg_rgb => "48;2;{r};{g};{b}m", r:u8, g:u8, b:u8;
+    ///
+    /// Not [downgraded](crate::style::color_depth), unlike [`Color::underln`](crate::style::Color::underln).
+    pub underln_clr_256color => "58;5;{val}m", val:u8;
+    ///
+    /// Not [downgraded](crate::style::color_depth), unlike [`Color::underln`](crate::style::Color::underln).
+    pub underln_clr_rgb => "58;2;{r};{g};{b}m", r:u8, g:u8, b:u8;
 }
 
 etty_macros::gen_csi! {
@@ -320,10 +334,32 @@ etty_macros::gen_csi! {
     pub evt_mouse_drag_set => "?1002h";
     pub evt_mouse_motion_set => "?1003h";
     pub evt_win_focus_set => "?1004h";
+    pub evt_paste_set => "?2004h";
 
     pub evt_mouse_rst => "?1000l";
     pub evt_mouse_ext_rst => "?1006l";
     pub evt_mouse_drag_rst => "?1002l";
     pub evt_mouse_motion_rst => "?1003l";
     pub evt_win_focus_rst => "?1004l";
+    pub evt_paste_rst => "?2004l";
+}
+
+/// [Kitty keyboard protocol][kitty] enhancement flags, combined with `|` and passed to
+/// [`evt_kbd_enh_push`] or [`evt_kbd_enh_set`].
+///
+/// [kitty]: https://sw.kovidgoyal.net/kitty/keyboard-protocol/#progressive-enhancement
+pub const KBD_ENH_DISAMBIGUATE: u8 = 1;
+/// Reports [`KeyKind::Repeat`](crate::evt::KeyKind::Repeat) and [`KeyKind::Release`](crate::evt::KeyKind::Release).
+pub const KBD_ENH_EVENT_TYPES: u8 = 2;
+pub const KBD_ENH_ALT_KEYS: u8 = 4;
+pub const KBD_ENH_ALL_KEYS: u8 = 8;
+/// Reports [`Key::text`](crate::evt::Key::text), requires [`KBD_ENH_ALL_KEYS`].
+pub const KBD_ENH_TEXT: u8 = 16;
+
+etty_macros::gen_csi! {
+    // mod kbd;
+    pub evt_kbd_enh_push => ">{flags}u", flags:u8;
+    pub evt_kbd_enh_pop => "<{n}u", n;
+    pub evt_kbd_enh_set => "={flags};{mode}u", flags:u8, mode:u8;
+    pub evt_kbd_enh_query => "?u";
 }
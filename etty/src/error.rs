@@ -0,0 +1,45 @@
+/// Error of the fallible `try_*` functions, e.g. [`try_raw_mode`](crate::term::try_raw_mode).
+#[derive(Debug)]
+pub enum Error {
+    /// The file descriptor isn't a terminal, or there is no controlling terminal to open.
+    NotATty,
+    Io(std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotATty => write!(f, "not a terminal"),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::NotATty => None,
+            Error::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        match err.raw_os_error() {
+            Some(libc::ENOTTY | libc::ENXIO) => Error::NotATty,
+            _ => Error::Io(err),
+        }
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NotATty => std::io::Error::from_raw_os_error(libc::ENOTTY),
+            Error::Io(err) => err,
+        }
+    }
+}
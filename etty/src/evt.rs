@@ -5,8 +5,6 @@
 //! [wiki-c0]: https://en.wikipedia.org/wiki/C0_and_C1_control_codes
 //! [mod-evt]: etty_macros::evt
 
-use std::io::Read;
-
 #[derive(Debug)]
 pub struct EventAndRaw {
     pub event: Event,
@@ -24,6 +22,23 @@ pub enum Event {
     Key(Key),
     Window(Window),
     Mouse(Mouse),
+    /// Text pasted while [`evt_paste_set`](crate::csi::evt_paste_set) is active.
+    Paste(String),
+    /// New terminal size in `(cols, rows)`, same as [`term_size`](crate::term::term_size).
+    Resize(u16, u16),
+    /// The process was continued after being stopped, e.g. by `Ctrl-Z` and `fg`. The screen
+    /// should be repainted, see [`suspend`](crate::guard::suspend).
+    Resumed,
+    /// Reply to a report request sent with the [`csi`](crate::csi) builders. OSC and DCS replies,
+    /// e.g. colours and the terminal version, are only parsed while a [`query`](crate::query)
+    /// waits for one.
+    ///
+    /// A cursor position report on the first row with a column from 1 to 16, e.g. `\x1b[1;5R`,
+    /// is indistinguishable from `F3` with or without modifiers and is decoded as the key. Use
+    /// [`query`](crate::query) to get it as a reply.
+    Reply(Reply),
+    /// Sequence which isn't recognized. Event streams also yield the input an
+    /// [`InputParser`] reports as a [`ParseError`] as this, so it never ends them.
     Undefined,
 }
 
@@ -33,64 +48,82 @@ pub enum Window {
     FocusOut,
 }
 
-#[derive(Debug)]
-pub enum Mouse {
-    Motion(u16, u16),
-
-    RlseBtn(u16, u16),
-
-    RlseLft(u16, u16),
-    RlseMid(u16, u16),
-    RlseRgt(u16, u16),
-
-    PrssLft(u16, u16),
-    PrssMid(u16, u16),
-    PrssRgt(u16, u16),
-
-    DragLft(u16, u16),
-    DragMid(u16, u16),
-    DragRgt(u16, u16),
-
-    PrssLftAlt(u16, u16),
-    PrssMidAlt(u16, u16),
-    PrssRgtAlt(u16, u16),
-
-    RlseLftAlt(u16, u16),
-    RlseMidAlt(u16, u16),
-    RlseRgtAlt(u16, u16),
-
-    DragLftAlt(u16, u16),
-    DragMidAlt(u16, u16),
-    DragRgtAlt(u16, u16),
-
-    PrssLftCtrl(u16, u16),
-    PrssMidCtrl(u16, u16),
-    PrssRgtCtrl(u16, u16),
-
-    RlseLftCtrl(u16, u16),
-    RlseMidCtrl(u16, u16),
-    RlseRgtCtrl(u16, u16),
-
-    DragLftCtrl(u16, u16),
-    DragMidCtrl(u16, u16),
-    DragRgtCtrl(u16, u16),
-
-    WheelUp(u16, u16),
-    WheelDn(u16, u16),
-
-    WheelUpShift(u16, u16),
-    WheelDnShift(u16, u16),
+/// Mouse event, decoded from the button byte of [X10 and SGR][xterm-mouse] mouse reports.
+///
+/// `col` and `row` are 1-based, same as [`cus_goto`](crate::csi::cus_goto).
+///
+/// [xterm-mouse]: https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Mouse-Tracking
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct Mouse {
+    pub kind: MouseKind,
+    /// `None` for motion without any button held and for X10 releases, which don't tell the button.
+    pub btn: Option<MouseBtn>,
+    pub mods: Mods,
+    pub col: u16,
+    pub row: u16,
+}
 
-    WheelUpAlt(u16, u16),
-    WheelDnAlt(u16, u16),
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum MouseKind {
+    Press,
+    Release,
+    Drag,
+    Move,
+    Scroll,
+}
 
-    WheelUpCtrl(u16, u16),
-    WheelDnCtrl(u16, u16),
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum MouseBtn {
+    Lft,
+    Mid,
+    Rgt,
+    WheelUp,
+    WheelDn,
+    WheelLft,
+    WheelRgt,
+    /// Buttons 8 ~ 11, e.g. back and forward.
+    Other(u8),
+}
 
-    UNIMPLEMENTED(u16, u16, u16),
+impl Mouse {
+    /// Decodes the button byte `cb`, `rlse` is only set by the SGR `m` final byte.
+    fn decode(cb: u16, col: u16, row: u16, rlse: bool) -> Self {
+        let btn = match (cb & 0b11) | ((cb & 0b1100_0000) >> 4) {
+            0 => Some(MouseBtn::Lft),
+            1 => Some(MouseBtn::Mid),
+            2 => Some(MouseBtn::Rgt),
+            3 => None,
+            4 => Some(MouseBtn::WheelUp),
+            5 => Some(MouseBtn::WheelDn),
+            6 => Some(MouseBtn::WheelLft),
+            7 => Some(MouseBtn::WheelRgt),
+            n => Some(MouseBtn::Other(n as u8)),
+        };
+        let kind = match btn {
+            Some(
+                MouseBtn::WheelUp | MouseBtn::WheelDn | MouseBtn::WheelLft | MouseBtn::WheelRgt,
+            ) => MouseKind::Scroll,
+            None if cb & 32 != 0 => MouseKind::Move,
+            _ if cb & 32 != 0 => MouseKind::Drag,
+            None => MouseKind::Release,
+            _ if rlse => MouseKind::Release,
+            _ => MouseKind::Press,
+        };
+        let mods = [(4, Mods::SHIFT), (8, Mods::ALT), (16, Mods::CTRL)]
+            .into_iter()
+            .filter(|(bit, _)| cb & bit != 0)
+            .fold(Mods::NONE, |acc, (_, mods)| acc | mods);
+        Self {
+            kind,
+            btn,
+            mods,
+            col,
+            row,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum Nav {
     Up,
     Dn,
@@ -107,23 +140,462 @@ pub enum Nav {
     BTab,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum Key {
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Key {
+    pub code: KeyCode,
+    pub mods: Mods,
+    /// Always [`KeyKind::Press`] unless [`KBD_ENH_EVENT_TYPES`][crate::csi::KBD_ENH_EVENT_TYPES] is pushed.
+    pub kind: KeyKind,
+    /// Text produced by the key, only reported with [`KBD_ENH_TEXT`][crate::csi::KBD_ENH_TEXT].
+    pub text: Option<String>,
+}
+
+impl Key {
+    pub const fn new(code: KeyCode, mods: Mods) -> Self {
+        Self {
+            code,
+            mods,
+            kind: KeyKind::Press,
+            text: None,
+        }
+    }
+}
+
+impl From<KeyCode> for Key {
+    fn from(code: KeyCode) -> Self {
+        Self::new(code, Mods::NONE)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum KeyCode {
     C0(crate::C0),
     Nav(Nav),
     F(u8),
-    Alt(char),
     Utf8(char),
+    /// [Kitty functional key][kitty-keys] without a variant of its own, e.g. keypad, media and modifier keys.
+    ///
+    /// [kitty-keys]: https://sw.kovidgoyal.net/kitty/keyboard-protocol/#functional-key-definitions
+    Kitty(u32),
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy)]
+pub enum KeyKind {
+    #[default]
+    Press,
+    Repeat,
+    Release,
+}
+
+/// Bitset of modifier keys held down with a [`Key`].
+///
+/// ```rust
+/// use etty::evt::Mods;
+///
+/// let mods = Mods::CTRL | Mods::SHIFT;
+/// assert!(mods.contains(Mods::CTRL));
+/// assert!(!mods.contains(Mods::ALT));
+/// assert!(Mods::NONE.is_empty());
+/// ```
+#[derive(Debug, Default, Eq, PartialEq, Hash, Clone, Copy)]
+pub struct Mods(u8);
+
+impl Mods {
+    pub const NONE: Mods = Mods(0);
+    pub const SHIFT: Mods = Mods(1);
+    pub const ALT: Mods = Mods(1 << 1);
+    pub const CTRL: Mods = Mods(1 << 2);
+    pub const META: Mods = Mods(1 << 3);
+    pub const SUPER: Mods = Mods(1 << 4);
+    pub const HYPER: Mods = Mods(1 << 5);
+    pub const CAPS_LOCK: Mods = Mods(1 << 6);
+    pub const NUM_LOCK: Mods = Mods(1 << 7);
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+    pub const fn contains(self, other: Mods) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Decodes the xterm modifier parameter, e.g. the `5` in `\x1b[1;5A`, which is `1` plus the
+    /// bits of shift, alt, ctrl and meta.
+    fn from_xterm(param: u32) -> Option<Mods> {
+        match param {
+            1..=16 => Some(Mods((param - 1) as u8)),
+            _ => None,
+        }
+    }
+
+    /// Decodes the kitty modifier parameter, which swaps the meaning of bit `8` to super.
+    ///
+    /// https://sw.kovidgoyal.net/kitty/keyboard-protocol/#modifiers
+    fn from_kitty(param: u32) -> Mods {
+        let bits = param.saturating_sub(1);
+        [
+            (1, Mods::SHIFT),
+            (2, Mods::ALT),
+            (4, Mods::CTRL),
+            (8, Mods::SUPER),
+            (16, Mods::HYPER),
+            (32, Mods::META),
+            (64, Mods::CAPS_LOCK),
+            (128, Mods::NUM_LOCK),
+        ]
+        .into_iter()
+        .filter(|(bit, _)| bits & bit != 0)
+        .fold(Mods::NONE, |acc, (_, mods)| acc | mods)
+    }
+}
+
+impl std::ops::BitOr for Mods {
+    type Output = Mods;
+    fn bitor(self, rhs: Mods) -> Mods {
+        Mods(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for Mods {
+    fn bitor_assign(&mut self, rhs: Mods) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Reply of the terminal to a report request, see [`query`](crate::query).
+///
+/// ```rust
+/// use etty::evt::{Event, InputParser, ModeStatus, Reply};
+///
+/// let mut parser = InputParser::new();
+/// parser.expect_replies(true);
+/// parser.feed(b"\x1b[12;40R\x1b[?2004;1$y\x1b]11;rgb:0000/8080/ffff\x1b\\");
+/// let mut replies = parser.map(|e| match e.unwrap().event {
+///     Event::Reply(reply) => reply,
+///     _ => panic!(),
+/// });
+/// assert_eq!(replies.next(), Some(Reply::CusPos { col: 40, row: 12 }));
+/// let status = ModeStatus::Set;
+/// assert_eq!(replies.next(), Some(Reply::Mode { mode: 2004, status }));
+/// assert_eq!(replies.next(), Some(Reply::BgColor(0, 128, 255)));
+/// ```
+///
+/// Device attributes, the terminal version, the other mode statuses, and colours of 1 to 4 hex
+/// digits per channel:
+///
+/// ```rust
+/// use etty::evt::{Event, InputParser, ModeStatus, Reply};
+///
+/// let mut parser = InputParser::new();
+/// parser.expect_replies(true);
+/// parser.feed(b"\x1b[?62;22;52c\x1b[>41;390;0c\x1bP>|XTerm(390)\x1b\\");
+/// parser.feed(b"\x1b[?1049;2$y\x1b[?25;0$y\x1b[?12;3$y\x1b[?7;4$y");
+/// parser.feed(b"\x1b]10;rgb:8/80/800\x07\x1b]11;rgb:f/ff/fff\x07\x1b]11;rgb:1/02/0003\x07");
+/// let mut replies = parser.map(|e| match e.unwrap().event {
+///     Event::Reply(reply) => reply,
+///     _ => panic!(),
+/// });
+/// assert_eq!(replies.next(), Some(Reply::DevAttr(vec![62, 22, 52])));
+/// assert_eq!(replies.next(), Some(Reply::DevAttr2 { kind: 41, version: 390 }));
+/// assert_eq!(replies.next(), Some(Reply::TermVer("XTerm(390)".to_string())));
+/// let modes = [(1049, ModeStatus::Rst), (25, ModeStatus::Unknown)];
+/// let modes = modes.into_iter().chain([(12, ModeStatus::PermSet), (7, ModeStatus::PermRst)]);
+/// for (mode, status) in modes {
+///     assert_eq!(replies.next(), Some(Reply::Mode { mode, status }));
+/// }
+/// assert_eq!(replies.next(), Some(Reply::FgColor(136, 128, 127)));
+/// assert_eq!(replies.next(), Some(Reply::BgColor(255, 255, 255)));
+/// assert_eq!(replies.next(), Some(Reply::BgColor(17, 2, 0)));
+/// ```
+///
+/// OSC and DCS replies are only parsed while [expected](InputParser::expect_replies), otherwise
+/// they're taken for `Alt` plus what follows:
+///
+/// ```rust
+/// use etty::evt::{Event, InputParser, KeyCode, Mods};
+///
+/// let mut parser = InputParser::new();
+/// parser.feed(b"\x1b]1");
+/// let Event::Key(key) = parser.next().unwrap().unwrap().event else { panic!() };
+/// assert_eq!((key.code, key.mods), (KeyCode::Utf8(']'), Mods::ALT));
+/// ```
+///
+/// A cursor position on the first row is only taken for `F3` if its column is a valid modifier
+/// parameter:
+///
+/// ```rust
+/// use etty::evt::{Event, InputParser, KeyCode, Mods, Reply};
+///
+/// let mut parser = InputParser::new();
+/// parser.feed(b"\x1b[1;5R\x1b[1;40R");
+/// let Event::Key(key) = parser.next().unwrap().unwrap().event else { panic!() };
+/// assert_eq!((key.code, key.mods), (KeyCode::F(3), Mods::CTRL));
+/// let Event::Reply(reply) = parser.next().unwrap().unwrap().event else { panic!() };
+/// assert_eq!(reply, Reply::CusPos { col: 40, row: 1 });
+/// ```
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Reply {
+    /// `CSI row ; col R`, 1-based, answers [`cus_pos_rpt`](crate::csi::cus_pos_rpt).
+    CusPos { col: u16, row: u16 },
+    /// `CSI ? level ; exts.. c`, answers [`dev_attr_rpt`](crate::csi::dev_attr_rpt).
+    DevAttr(Vec<u16>),
+    /// `CSI > kind ; version ; rom c`, answers [`dev_attr2_rpt`](crate::csi::dev_attr2_rpt).
+    DevAttr2 { kind: u16, version: u16 },
+    /// `DCS > | name ST`, answers [`term_ver_rpt`](crate::csi::term_ver_rpt).
+    TermVer(String),
+    /// `CSI ? mode ; status $ y`, answers [`mode_rpt`](crate::csi::mode_rpt).
+    Mode { mode: u16, status: ModeStatus },
+    /// `OSC 10 ; rgb:r/g/b ST`, the default foreground colour.
+    FgColor(u8, u8, u8),
+    /// `OSC 11 ; rgb:r/g/b ST`, the default background colour.
+    BgColor(u8, u8, u8),
+    /// `CSI ? flags u`, answers [`evt_kbd_enh_query`](crate::csi::evt_kbd_enh_query) with the
+    /// `KBD_ENH_*` flags in effect.
+    KbdEnh(u8),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ModeStatus {
+    /// The terminal doesn't know the mode.
+    Unknown,
+    Set,
+    Rst,
+    PermSet,
+    PermRst,
+}
+
+impl Reply {
+    /// Decodes the raw bytes of a reply, regardless of what the parser made of them.
+    pub(crate) fn decode(raw: &[u8]) -> Option<Reply> {
+        if let Some(body) = raw.strip_prefix(b"\x1b[") {
+            let (&fin, body) = body.split_last()?;
+            let (prefix, body) = match body.split_first() {
+                Some((&b @ (b'?' | b'>'), body)) => (Some(b), body),
+                _ => (None, body),
+            };
+            let (body, dollar) = match body.strip_suffix(b"$") {
+                Some(body) => (body, true),
+                None => (body, false),
+            };
+            let params = csi_params(body)?
+                .into_iter()
+                .map(|field| match field.as_slice() {
+                    [n] => u16::try_from(*n).ok(),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            let reply = match (prefix, dollar, fin, params.as_slice()) {
+                (None, false, b'R', &[row, col]) => Reply::CusPos { col, row },
+                (Some(b'?'), false, b'c', [_, ..]) => Reply::DevAttr(params),
+                (Some(b'?'), false, b'u', &[flags]) => Reply::KbdEnh(u8::try_from(flags).ok()?),
+                (Some(b'>'), false, b'c', &[kind, version, ..]) => {
+                    Reply::DevAttr2 { kind, version }
+                }
+                (Some(b'?'), true, b'y', &[mode, status]) => {
+                    let status = match status {
+                        0 => ModeStatus::Unknown,
+                        1 => ModeStatus::Set,
+                        2 => ModeStatus::Rst,
+                        3 => ModeStatus::PermSet,
+                        4 => ModeStatus::PermRst,
+                        _ => return None,
+                    };
+                    Reply::Mode { mode, status }
+                }
+                _ => return None,
+            };
+            return Some(reply);
+        }
+        if let Some(body) = raw.strip_prefix(b"\x1b]") {
+            let body = body
+                .strip_suffix(b"\x07")
+                .or_else(|| body.strip_suffix(b"\x1b\\"))?;
+            let reply = match body.split_at_checked(3)? {
+                (b"10;", spec) => {
+                    let (r, g, b) = osc_rgb(spec)?;
+                    Reply::FgColor(r, g, b)
+                }
+                (b"11;", spec) => {
+                    let (r, g, b) = osc_rgb(spec)?;
+                    Reply::BgColor(r, g, b)
+                }
+                _ => return None,
+            };
+            return Some(reply);
+        }
+        if let Some(body) = raw.strip_prefix(b"\x1bP>|") {
+            let name = body.strip_suffix(b"\x1b\\")?;
+            return Some(Reply::TermVer(String::from_utf8_lossy(name).into_owned()));
+        }
+        None
+    }
+}
+
+/// Decodes `rgb:r/g/b` of 1 to 4 hex digits each, scaled to 8 bits.
+///
+/// https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h3-Operating-System-Commands
+fn osc_rgb(spec: &[u8]) -> Option<(u8, u8, u8)> {
+    let spec = std::str::from_utf8(spec.strip_prefix(b"rgb:")?).ok()?;
+    let mut comps = spec.split('/').map(|hex| {
+        if !(1..=4).contains(&hex.len()) {
+            return None;
+        }
+        let val = u32::from_str_radix(hex, 16).ok()?;
+        let max = (1_u32 << (4 * hex.len())) - 1;
+        Some((val * 255 / max) as u8)
+    });
+    let rgb = (comps.next()??, comps.next()??, comps.next()??);
+    comps.next().is_none().then_some(rgb)
 }
 
 pub fn event_stream() -> EventAndRawStream {
-    event_and_raw_stream(std::io::stdin())
+    stream_builder().init()
+}
+
+/// Builder for [`EventAndRawStream`] and [`AsyncEventAndRawStream`].
+///
+/// Streams read `/dev/tty` unless given another [`Tty`](crate::term::Tty), so the input is still
+/// the terminal's while stdin is redirected.
+///
+/// ```rust,no_run
+/// let events = etty::evt::stream_builder()
+///     .esc_timeout(std::time::Duration::from_millis(50))
+///     .init();
+/// ```
+pub fn stream_builder() -> StreamBuilder<'static> {
+    StreamBuilder {
+        esc_timeout: std::time::Duration::from_millis(25),
+        job_control: false,
+        kbd_enh: None,
+        tty: None,
+    }
+}
+
+pub struct StreamBuilder<'a> {
+    esc_timeout: std::time::Duration,
+    job_control: bool,
+    kbd_enh: Option<u8>,
+    tty: Option<&'a crate::term::Tty>,
+}
+
+impl<'a> StreamBuilder<'a> {
+    /// How long to wait for the rest of a sequence after its last byte. Defaults to 25ms.
+    ///
+    /// An `ESC` followed by another byte within the timeout is read as `Alt` plus that key, or as
+    /// the start of a CSI/SS3 sequence. Once the timeout elapses, an `ESC` on its own is the key
+    /// itself and an incomplete sequence is yielded as it is.
+    ///
+    /// Pastes and replies are waited for until their terminator, however slowly they arrive, see
+    /// [`InputParser::pending_ambiguous`].
+    pub fn esc_timeout(&mut self, timeout: std::time::Duration) -> &mut Self {
+        self.esc_timeout = timeout;
+        self
+    }
+    /// Whether `Ctrl-Z` and `SIGTSTP` [suspend](crate::guard::suspend) the process. Defaults to
+    /// `false`.
+    ///
+    /// The `Ctrl-Z` key is taken out of the stream, and [`Event::Resumed`] is yielded once the
+    /// process is continued.
+    pub fn job_control(&mut self, on: bool) -> &mut Self {
+        self.job_control = on;
+        self
+    }
+    /// `KBD_ENH_*` flags the input is decoded with, see [`InputParser::kbd_enh`].
+    ///
+    /// Unless set, the stream follows the flags pushed with
+    /// [`Csi::out`](crate::csi::Csi::out) and the like, see [`kbd_enh`](crate::guard::kbd_enh).
+    pub fn kbd_enh(&mut self, flags: u8) -> &mut Self {
+        self.kbd_enh = Some(flags);
+        self
+    }
+    /// Terminal to read the input from and to query the size of, `/dev/tty` by default.
+    ///
+    /// The stream reads a handle of its own, and [`init_async`](StreamBuilder::init_async) opens
+    /// the terminal anew by its name, so the non-blocking mode it needs isn't shared with `tty`.
+    pub fn tty(&mut self, tty: &'a crate::term::Tty) -> &mut Self {
+        self.tty = Some(tty);
+        self
+    }
+    pub fn init(&mut self) -> EventAndRawStream {
+        event_and_raw_stream(
+            self.open_tty(),
+            self.esc_timeout,
+            self.job_control,
+            self.kbd_enh,
+        )
+    }
+    /// Async counterpart of [`StreamBuilder::init`], see [`async_event_stream`].
+    pub fn init_async(&mut self) -> std::io::Result<AsyncEventAndRawStream> {
+        // a file description of its own, a dup would share `O_NONBLOCK` with the caller's handle.
+        let tty = match self.tty {
+            Some(tty) => crate::unix::reopen_tty(std::os::unix::io::AsRawFd::as_raw_fd(tty))?,
+            None => crate::unix::get_tty_file()?,
+        };
+        crate::unix::set_nonblocking(&tty)?;
+        let sig_pipe = |sig| {
+            let pipe = crate::signal::SigPipe::new(sig)?;
+            pipe.set_nonblocking()?;
+            async_fd(pipe)
+        };
+        let winch = sig_pipe(libc::SIGWINCH)?;
+        let cont = sig_pipe(libc::SIGCONT)?;
+        let tstp = match self.job_control {
+            true => Some(sig_pipe(libc::SIGTSTP)?),
+            false => None,
+        };
+        let (reader, stash) = crate::query::Reader::register(false);
+        let mut parser = InputParser::new();
+        parser.kbd_enh(self.kbd_enh.unwrap_or_default());
+        parser.feed(&stash);
+        Ok(AsyncEventAndRawStream {
+            tty: async_fd(tty)?,
+            winch,
+            cont,
+            tstp,
+            _reader: reader,
+            parser,
+            eof: false,
+            esc_timeout: self.esc_timeout,
+            esc_deadline: None,
+            job_control: self.job_control,
+            kbd_enh: self.kbd_enh,
+        })
+    }
+
+    // a handle of its own, which the stream reads unbuffered.
+    fn open_tty(&self) -> std::io::Result<std::fs::File> {
+        match self.tty {
+            Some(tty) => Ok(tty.try_clone()?.into()),
+            None => crate::unix::get_tty_file(),
+        }
+    }
 }
 
 pub struct EventOnlyStream {
     stream: EventAndRawStream,
 }
 
+impl EventOnlyStream {
+    /// See [`EventAndRawStream::close`].
+    pub fn close(self) {}
+    /// See [`EventAndRawStream::poll`].
+    pub fn poll(&self, timeout: std::time::Duration) -> std::io::Result<bool> {
+        self.stream.poll(timeout)
+    }
+    /// See [`EventAndRawStream::try_next`].
+    pub fn try_next(&mut self) -> std::io::Result<Option<Event>> {
+        self.stream.try_next().map(|e| e.map(|e| e.event))
+    }
+    /// See [`EventAndRawStream::next_timeout`].
+    pub fn next_timeout(&mut self, timeout: std::time::Duration) -> std::io::Result<Option<Event>> {
+        self.stream
+            .next_timeout(timeout)
+            .map(|e| e.map(|e| e.event))
+    }
+}
+
 impl Iterator for EventOnlyStream {
     type Item = std::io::Result<Event>;
     fn next(&mut self) -> Option<Self::Item> {
@@ -131,28 +603,281 @@ impl Iterator for EventOnlyStream {
     }
 }
 
-fn event_and_raw_stream<R>(reader: R) -> EventAndRawStream
+/// `reader` must be unbuffered, it's only read once `poll` tells it has input.
+fn event_and_raw_stream<R>(
+    reader: std::io::Result<R>,
+    esc_timeout: std::time::Duration,
+    job_control: bool,
+    kbd_enh: Option<u8>,
+) -> EventAndRawStream
 where
-    R: std::io::Read + Send + 'static,
+    R: std::io::Read + std::os::unix::io::AsRawFd + Send + 'static,
 {
+    use std::os::unix::io::AsRawFd;
+
     let (tx, rx) = crossbeam::channel::unbounded::<std::io::Result<EventAndRaw>>();
-    let _ = std::thread::spawn(move || {
-        let mut parse = parser(reader);
-        while let Some(res) = parse() {
-            tx.send(res).unwrap()
+    let (reader, (wake_rx, wake_tx)) = match reader.and_then(|r| Ok((r, crate::unix::pipe()?))) {
+        Ok(res) => res,
+        Err(err) => {
+            let _ = tx.send(Err(err));
+            return EventAndRawStream {
+                rx,
+                wake: None,
+                jh: None,
+            };
+        }
+    };
+    let sig_pipe = |sig| {
+        crate::signal::SigPipe::new(sig)
+            .map_err(|err| {
+                let _ = tx.send(Err(err));
+            })
+            .ok()
+    };
+    let winch = sig_pipe(libc::SIGWINCH);
+    let cont = sig_pipe(libc::SIGCONT);
+    let tstp = job_control.then(|| sig_pipe(libc::SIGTSTP)).flatten();
+    let (registration, stash) = crate::query::Reader::register(true);
+    let jh = std::thread::spawn(move || {
+        let _registration = registration;
+        let mut reader = reader;
+        let mut parser = InputParser::new();
+        parser.kbd_enh(kbd_enh.unwrap_or_default());
+        parser.feed(&stash);
+        let mut buf = [0_u8; 1024];
+        let fd =
+            |pipe: &Option<crate::signal::SigPipe>| pipe.as_ref().map_or(-1, |p| p.as_raw_fd());
+        loop {
+            let timeout = parser.pending_ambiguous().then_some(esc_timeout);
+            let fds = [
+                reader.as_raw_fd(),
+                fd(&winch),
+                fd(&cont),
+                fd(&tstp),
+                crate::query::pause_fd(),
+                wake_rx.as_raw_fd(),
+            ];
+            let [input, winched, conted, stopped, paused, woken] =
+                match crate::unix::poll_read(fds, timeout) {
+                    Ok(ready) => ready,
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                        return;
+                    }
+                };
+            if woken {
+                return;
+            }
+            if paused {
+                crate::query::park();
+                continue;
+            }
+            if let (true, Some(winch)) = (winched, &winch) {
+                let _ = winch.wait();
+                if tx.send(resize_event(reader.as_raw_fd())).is_err() {
+                    return;
+                }
+            }
+            if let (true, Some(cont)) = (conted, &cont) {
+                let _ = cont.wait();
+                if tx
+                    .send(Ok(EventAndRaw::new(Event::Resumed, Vec::new())))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            if let (true, Some(tstp)) = (stopped, &tstp) {
+                let _ = tstp.wait();
+                if let Err(err) = crate::guard::suspend() {
+                    if tx.send(Err(err.into())).is_err() {
+                        return;
+                    }
+                }
+            }
+            // `None` once the escape timeout elapses.
+            let n = match (input, winched || conted || stopped) {
+                (true, _) => match reader.read(&mut buf) {
+                    Ok(n) => Some(n),
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                        return;
+                    }
+                },
+                (false, true) => continue,
+                (false, false) => None,
+            };
+            if let Some(n) = n {
+                parser.feed(&buf[..n]);
+            }
+            let eof = n == Some(0);
+            let flush = eof || n.is_none();
+            parser.expect_replies(crate::query::waiting());
+            if kbd_enh.is_none() {
+                parser.kbd_enh(crate::guard::kbd_enh());
+            }
+            while let Some(res) = if flush { parser.flush() } else { parser.next() } {
+                let Some(res) = sift(res, job_control) else {
+                    continue;
+                };
+                if tx.send(res).is_err() {
+                    return;
+                }
+            }
+            if eof {
+                return;
+            }
         }
     });
-    EventAndRawStream { rx }
+    EventAndRawStream {
+        rx,
+        wake: Some(wake_tx),
+        jh: Some(jh),
+    }
+}
+
+/// Takes replies to queries, and with job control the `Ctrl-Z` key, out of the stream.
+///
+/// Malformed input is yielded as [`Event::Undefined`], errors of the stream are only the ones of
+/// reading the input.
+fn sift(res: Parsed, job_control: bool) -> Option<std::io::Result<EventAndRaw>> {
+    let event = match res {
+        Ok(event) => crate::query::route(event)?,
+        Err(err) => return Some(Ok(EventAndRaw::new(Event::Undefined, err.raw))),
+    };
+    if job_control && is_suspend_key(&event.event) {
+        return crate::guard::suspend().err().map(|err| Err(err.into()));
+    }
+    Some(Ok(event))
+}
+
+/// `Ctrl-Z`, either as the legacy `SUB` byte or as a kitty protocol key.
+fn is_suspend_key(event: &Event) -> bool {
+    let Event::Key(key) = event else {
+        return false;
+    };
+    // the kitty protocol reports the lock keys along with the modifiers.
+    let mods = Mods(key.mods.0 & !(Mods::CAPS_LOCK.0 | Mods::NUM_LOCK.0));
+    let suspend = match key.code {
+        KeyCode::C0(crate::C0::Sub) => mods.is_empty(),
+        KeyCode::Utf8('z') => mods == Mods::CTRL,
+        _ => false,
+    };
+    suspend && key.kind == KeyKind::Press
+}
+
+fn resize_event(fd: std::os::unix::io::RawFd) -> std::io::Result<EventAndRaw> {
+    let size = crate::unix::get_term_size(fd)?;
+    let event = Event::Resize(size.col as u16, size.row as u16);
+    Ok(EventAndRaw::new(event, Vec::new()))
 }
 
+/// Blocking event stream, read by a background thread.
+///
+/// The thread is stopped and joined when the stream is dropped or [`closed`](EventAndRawStream::close),
+/// after which nothing reads from the input anymore.
 pub struct EventAndRawStream {
     rx: crossbeam::channel::Receiver<std::io::Result<EventAndRaw>>,
+    // dropping it hangs up the pipe the reader thread polls on.
+    wake: Option<std::fs::File>,
+    jh: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for EventAndRawStream {
+    fn drop(&mut self) {
+        self.wake.take();
+        if let Some(jh) = self.jh.take() {
+            let _ = jh.join();
+        }
+    }
 }
 
 impl EventAndRawStream {
     pub fn event_only(self) -> EventOnlyStream {
         EventOnlyStream { stream: self }
     }
+
+    /// Stops the reader thread and waits for it to exit, same as dropping the stream.
+    pub fn close(self) {}
+
+    /// Waits up to `timeout` for an event, without taking it out of the stream.
+    ///
+    /// Returns `true` if the next call to [`Iterator::next`] won't block, and an
+    /// [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof) error once the stream has ended.
+    ///
+    /// ```rust,no_run
+    /// fn main() -> std::io::Result<()> {
+    ///     let mut events = etty::event_stream();
+    ///     loop {
+    ///         let deadline = std::time::Duration::from_millis(16);
+    ///         while events.poll(deadline)? {
+    ///             if let Some(event) = events.next() {
+    ///                 println!("{:?}\r", event?.event);
+    ///             }
+    ///         }
+    ///         // render the next frame
+    ///     }
+    /// }
+    /// ```
+    pub fn poll(&self, timeout: std::time::Duration) -> std::io::Result<bool> {
+        let mut sel = crossbeam::channel::Select::new();
+        sel.recv(&self.rx);
+        match sel.ready_timeout(timeout) {
+            Err(_timeout) => Ok(false),
+            // a ready but empty channel is a disconnected one.
+            Ok(_) if self.rx.is_empty() => Err(ended()),
+            Ok(_) => Ok(true),
+        }
+    }
+
+    /// Takes the next event if there is one already, without blocking.
+    ///
+    /// Returns `Ok(None)` if there is none yet, and an
+    /// [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof) error once the stream has ended, same
+    /// with [`poll`](EventAndRawStream::poll).
+    pub fn try_next(&mut self) -> std::io::Result<Option<EventAndRaw>> {
+        match self.rx.try_recv() {
+            Ok(res) => res.map(Some),
+            Err(crossbeam::channel::TryRecvError::Empty) => Ok(None),
+            Err(crossbeam::channel::TryRecvError::Disconnected) => Err(ended()),
+        }
+    }
+
+    /// Same with [`Iterator::next`] but gives up after `timeout` with `Ok(None)`.
+    ///
+    /// Returns an [`UnexpectedEof`](std::io::ErrorKind::UnexpectedEof) error once the stream has
+    /// ended, so a loop on it doesn't spin.
+    ///
+    /// ```rust,no_run
+    /// let mut events = etty::event_stream();
+    /// loop {
+    ///     match events.next_timeout(std::time::Duration::from_millis(16)) {
+    ///         Ok(Some(event)) => println!("{:?}\r", event),
+    ///         Ok(None) => {} // render the next frame
+    ///         // the input can't be read anymore.
+    ///         Err(_) => break,
+    ///     }
+    /// }
+    /// ```
+    pub fn next_timeout(
+        &mut self,
+        timeout: std::time::Duration,
+    ) -> std::io::Result<Option<EventAndRaw>> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(res) => res.map(Some),
+            Err(crossbeam::channel::RecvTimeoutError::Timeout) => Ok(None),
+            Err(crossbeam::channel::RecvTimeoutError::Disconnected) => Err(ended()),
+        }
+    }
+}
+
+fn ended() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "`etty::evt::EventAndRawStream` has ended",
+    )
 }
 
 impl Iterator for EventAndRawStream {
@@ -162,381 +887,1043 @@ impl Iterator for EventAndRawStream {
     }
 }
 
-fn parser<R>(mut reader: R) -> impl FnMut() -> Option<std::io::Result<EventAndRaw>>
+/// Async counterpart of [`event_stream`], driven by the readiness of `/dev/tty` on the tokio reactor.
+///
+/// Must be called within a tokio runtime with IO and time enabled.
+///
+/// ```rust,no_run
+/// use futures_util::StreamExt;
+///
+/// #[tokio::main(flavor = "current_thread")]
+/// async fn main() -> std::io::Result<()> {
+///     let _raw = etty::raw_mode();
+///     let mut events = etty::evt::async_event_stream()?.event_only();
+///     while let Some(event) = events.next().await {
+///         println!("{:?}\r", event?);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn async_event_stream() -> std::io::Result<AsyncEventAndRawStream> {
+    stream_builder().init_async()
+}
+
+/// Registers `inner` on the tokio reactor.
+fn async_fd<T>(inner: T) -> std::io::Result<tokio::io::unix::AsyncFd<T>>
 where
-    R: std::io::Read,
+    T: std::os::unix::io::AsRawFd,
 {
-    let mut frag: Option<u8> = None;
-    let mut buf = [0_u8; 2];
-    move || -> Option<std::io::Result<EventAndRaw>> {
-        if let Some(frag) = frag.take() {
-            let msg = parse(frag, &mut (&mut reader).bytes());
-            return Some(msg);
-        }
-        let res = match reader.read(&mut buf) {
-            Ok(0) => return None,
-            Ok(1) => {
-                if buf[0] == b'\x1b' {
-                    let event = Event::Key(Key::C0(crate::C0::from(buf[0])));
-                    Ok(EventAndRaw::new(event, vec![buf[0]]))
-                } else {
-                    parse(buf[0], &mut (&mut reader).bytes())
+    // SAFETY: the files and pipes registered own their fd, which stays open until the `AsyncFd`
+    // drops them.
+    unsafe { Ok(tokio::io::unix::AsyncFd::register(inner)?) }
+}
+
+pub struct AsyncEventAndRawStream {
+    tty: tokio::io::unix::AsyncFd<std::fs::File>,
+    winch: tokio::io::unix::AsyncFd<crate::signal::SigPipe>,
+    cont: tokio::io::unix::AsyncFd<crate::signal::SigPipe>,
+    tstp: Option<tokio::io::unix::AsyncFd<crate::signal::SigPipe>>,
+    _reader: crate::query::Reader,
+    parser: InputParser,
+    eof: bool,
+    esc_timeout: std::time::Duration,
+    esc_deadline: Option<std::pin::Pin<Box<tokio::time::Sleep>>>,
+    job_control: bool,
+    kbd_enh: Option<u8>,
+}
+
+impl AsyncEventAndRawStream {
+    pub fn event_only(self) -> AsyncEventOnlyStream {
+        AsyncEventOnlyStream { stream: self }
+    }
+}
+
+impl futures_core::Stream for AsyncEventAndRawStream {
+    type Item = std::io::Result<EventAndRaw>;
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        use std::future::Future;
+        use std::io::Read;
+        use std::task::Poll;
+
+        let this = self.get_mut();
+        loop {
+            this.parser.expect_replies(crate::query::waiting());
+            if this.kbd_enh.is_none() {
+                this.parser.kbd_enh(crate::guard::kbd_enh());
+            }
+            let event = match this.eof {
+                true => this.parser.flush(),
+                false => this.parser.next(),
+            };
+            if let Some(res) = event {
+                match sift(res, this.job_control) {
+                    Some(res) => return Poll::Ready(Some(res)),
+                    None => continue,
+                }
+            }
+            if this.parser.pending_ambiguous() {
+                let timeout = this.esc_timeout;
+                let deadline = this
+                    .esc_deadline
+                    .get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+                if deadline.as_mut().poll(cx).is_ready() {
+                    this.esc_deadline = None;
+                    if let Some(res) = this.parser.flush() {
+                        match sift(res, this.job_control) {
+                            Some(res) => return Poll::Ready(Some(res)),
+                            None => continue,
+                        }
+                    }
+                }
+            }
+            if let Poll::Ready(guard) = this.winch.poll_read_ready(cx) {
+                match guard?.try_io(|winch| winch.get_ref().wait()) {
+                    Ok(Ok(())) => {
+                        let fd = std::os::unix::io::AsRawFd::as_raw_fd(this.tty.get_ref());
+                        return Poll::Ready(Some(resize_event(fd)));
+                    }
+                    Ok(Err(err)) => return Poll::Ready(Some(Err(err))),
+                    Err(_would_block) => continue,
+                }
+            }
+            if let Poll::Ready(guard) = this.cont.poll_read_ready(cx) {
+                match guard?.try_io(|cont| cont.get_ref().wait()) {
+                    Ok(Ok(())) => {
+                        let event = EventAndRaw::new(Event::Resumed, Vec::new());
+                        return Poll::Ready(Some(Ok(event)));
+                    }
+                    Ok(Err(err)) => return Poll::Ready(Some(Err(err))),
+                    Err(_would_block) => continue,
+                }
+            }
+            if let Some(Poll::Ready(guard)) =
+                this.tstp.as_ref().map(|tstp| tstp.poll_read_ready(cx))
+            {
+                match guard?.try_io(|tstp| tstp.get_ref().wait()) {
+                    Ok(Ok(())) => {
+                        if let Err(err) = crate::guard::suspend() {
+                            return Poll::Ready(Some(Err(err.into())));
+                        }
+                    }
+                    Ok(Err(err)) => return Poll::Ready(Some(Err(err))),
+                    Err(_would_block) => {}
+                }
+                continue;
+            }
+            if this.eof {
+                return Poll::Ready(None);
+            }
+            if crate::query::paused(cx.waker()) {
+                return Poll::Pending;
+            }
+            let mut guard = std::task::ready!(this.tty.poll_read_ready(cx))?;
+            let mut buf = [0_u8; 1024];
+            match guard.try_io(|tty| tty.get_ref().read(&mut buf)) {
+                Ok(Ok(0)) => this.eof = true,
+                Ok(Ok(n)) => {
+                    this.parser.feed(&buf[..n]);
+                    this.esc_deadline = None;
                 }
+                Ok(Err(err)) => return Poll::Ready(Some(Err(err))),
+                Err(_would_block) => continue,
             }
-            Ok(2) => {
-                let mut sec_b = [buf[1]].into_iter();
-                let mut stream = (&mut sec_b).map(Ok).chain((&mut reader).bytes());
-                let res = parse(buf[0], &mut stream);
-                frag = sec_b.next();
-                assert!(sec_b.next().is_none());
-                res
+        }
+    }
+}
+
+pub struct AsyncEventOnlyStream {
+    stream: AsyncEventAndRawStream,
+}
+
+impl futures_core::Stream for AsyncEventOnlyStream {
+    type Item = std::io::Result<Event>;
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        std::pin::Pin::new(&mut self.stream)
+            .poll_next(cx)
+            .map(|opt| opt.map(|res| res.map(|e| e.event)))
+    }
+}
+
+/// Push based, sans-IO input parser.
+///
+/// Bytes are fed in arbitrary chunks, e.g. as they arrive from a socket or a PTY, and parsed
+/// events are taken out by iterating. An incomplete sequence stays buffered until the rest of it
+/// is fed, or until [`InputParser::flush`] gives up waiting for it.
+///
+/// ```rust
+/// use etty::evt::{Event, InputParser, KeyCode, Mods, Nav};
+///
+/// let mut parser = InputParser::new();
+/// parser.feed(b"a\x1b[1;");
+/// let event = parser.next().unwrap().unwrap();
+/// assert_eq!(event.raw, b"a");
+/// assert!(parser.next().is_none());
+///
+/// parser.feed(b"5A");
+/// let Some(Ok(Event::Key(key))) = parser.next().map(|e| e.map(|e| e.event)) else { panic!() };
+/// assert_eq!(key.code, KeyCode::Nav(Nav::Up));
+/// assert_eq!(key.mods, Mods::CTRL);
+/// ```
+///
+/// Modifiers of keys ending with `~`, e.g. `Shift+F5` and `Alt+PgDn`:
+///
+/// ```rust
+/// use etty::evt::{Event, InputParser, KeyCode, Mods, Nav};
+///
+/// let mut parser = InputParser::new();
+/// parser.feed(b"\x1b[15;2~\x1b[6;3~\x1b[3;6~");
+/// let mut keys = parser.map(|e| match e.unwrap().event {
+///     Event::Key(key) => (key.code, key.mods),
+///     _ => panic!(),
+/// });
+/// assert_eq!(keys.next(), Some((KeyCode::F(5), Mods::SHIFT)));
+/// assert_eq!(keys.next(), Some((KeyCode::Nav(Nav::PgDn), Mods::ALT)));
+/// assert_eq!(keys.next(), Some((KeyCode::Nav(Nav::Del), Mods::CTRL | Mods::SHIFT)));
+/// ```
+///
+/// An `ESC` can't be told from the start of a sequence until more bytes arrive, or a timeout
+/// elapses and it's flushed:
+///
+/// ```rust
+/// use etty::evt::{Event, InputParser, KeyCode, Mods};
+/// use etty::C0;
+///
+/// let mut parser = InputParser::new();
+/// parser.feed(b"\x1b");
+/// assert!(parser.next().is_none());
+/// assert_eq!(parser.pending(), b"\x1b");
+///
+/// let Some(Ok(Event::Key(key))) = parser.flush().map(|e| e.map(|e| e.event)) else { panic!() };
+/// assert_eq!((key.code, key.mods), (KeyCode::C0(C0::Esc), Mods::NONE));
+///
+/// parser.feed(b"\x1bx");
+/// let Some(Ok(Event::Key(key))) = parser.next().map(|e| e.map(|e| e.event)) else { panic!() };
+/// assert_eq!((key.code, key.mods), (KeyCode::Utf8('x'), Mods::ALT));
+/// ```
+///
+/// Kitty keyboard protocol, bracketed paste and mouse reports:
+///
+/// ```rust
+/// use etty::evt::{Event, InputParser, KeyCode, KeyKind, Mods, MouseBtn, MouseKind};
+///
+/// let mut parser = InputParser::new();
+/// parser.feed(b"\x1b[105;5:3u\x1b[200~hi\x1b[201~\x1b[<66;12;5M");
+/// let mut events = parser.map(|e| e.unwrap().event);
+///
+/// let Some(Event::Key(key)) = events.next() else { panic!() };
+/// assert_eq!((key.code, key.mods, key.kind), (KeyCode::Utf8('i'), Mods::CTRL, KeyKind::Release));
+///
+/// let Some(Event::Paste(text)) = events.next() else { panic!() };
+/// assert_eq!(text, "hi");
+///
+/// let Some(Event::Mouse(mouse)) = events.next() else { panic!() };
+/// assert_eq!((mouse.kind, mouse.btn), (MouseKind::Scroll, Some(MouseBtn::WheelLft)));
+/// assert_eq!((mouse.col, mouse.row), (12, 5));
+/// ```
+///
+/// A paste fed in pieces comes out whole, even if its end is split between two pieces:
+///
+/// ```rust
+/// use etty::evt::{Event, InputParser};
+///
+/// let mut parser = InputParser::new();
+/// parser.feed(b"\x1b[200~");
+/// for _ in 0..1000 {
+///     parser.feed(b"0123456789");
+///     assert!(parser.next().is_none());
+/// }
+/// parser.feed(b"\x1b[20");
+/// assert!(parser.next().is_none());
+/// parser.feed(b"1~");
+/// let Some(Ok(Event::Paste(text))) = parser.next().map(|e| e.map(|e| e.event)) else { panic!() };
+/// assert_eq!(text.len(), 10000);
+/// ```
+///
+/// Malformed input is reported without swallowing the sequence after it:
+///
+/// ```rust
+/// use etty::evt::{Event, InputParser, KeyCode, Nav, ParseErrorKind};
+///
+/// let mut parser = InputParser::new();
+/// parser.feed(b"\xff\x1b[1\x1b[A\x1b[<0;1");
+///
+/// let err = parser.next().unwrap().unwrap_err();
+/// assert_eq!((err.kind, err.raw), (ParseErrorKind::InvalidUtf8, b"\xff".to_vec()));
+///
+/// let err = parser.next().unwrap().unwrap_err();
+/// assert_eq!((err.kind, err.raw), (ParseErrorKind::Malformed, b"\x1b[1".to_vec()));
+///
+/// let Some(Ok(Event::Key(key))) = parser.next().map(|e| e.map(|e| e.event)) else { panic!() };
+/// assert_eq!(key.code, KeyCode::Nav(Nav::Up));
+///
+/// assert!(parser.next().is_none());
+/// let err = parser.flush().unwrap().unwrap_err();
+/// assert_eq!(err.kind, ParseErrorKind::Incomplete);
+/// ```
+#[derive(Debug, Default)]
+pub struct InputParser {
+    buf: Vec<u8>,
+    /// Length of the front of `buf` known not to finish the pending sequence.
+    scanned: usize,
+    replies: bool,
+    kbd_enh: u8,
+}
+
+impl InputParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Bytes fed but not yielded yet, because they might be the start of a longer sequence.
+    pub fn pending(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Whether the [`pending`](InputParser::pending) bytes are to be [flushed](InputParser::flush)
+    /// once a timeout elapses, e.g. an `ESC` which might be the key itself or the start of a
+    /// sequence, rather than waited for until more bytes arrive.
+    ///
+    /// `false` if there are none, and for a bracketed paste or a reply string, which always end
+    /// with a terminator but may arrive in pieces far apart.
+    ///
+    /// ```rust
+    /// use etty::evt::InputParser;
+    ///
+    /// let mut parser = InputParser::new();
+    /// parser.feed(b"\x1b[1;");
+    /// assert!(parser.next().is_none());
+    /// assert!(parser.pending_ambiguous());
+    ///
+    /// let mut parser = InputParser::new();
+    /// parser.feed(b"\x1b[200~hello ");
+    /// assert!(parser.next().is_none());
+    /// assert!(!parser.pending_ambiguous());
+    /// ```
+    pub fn pending_ambiguous(&self) -> bool {
+        !self.buf.is_empty() && pending_ends(&self.buf, self.replies).is_none()
+    }
+
+    /// Whether `ESC ]` and `ESC P` followed by a digit start OSC and DCS replies, rather than
+    /// being `Alt` plus the key typed after it. Defaults to `false`.
+    ///
+    /// Event streams turn it on while a [`query`](crate::query) waits for its reply.
+    pub fn expect_replies(&mut self, on: bool) -> &mut Self {
+        self.replies = on;
+        self
+    }
+
+    /// `KBD_ENH_*` flags of the [kitty keyboard protocol](crate::csi::KBD_ENH_DISAMBIGUATE) in
+    /// effect. Defaults to `0`, and is taken over from every [`Reply::KbdEnh`] parsed.
+    ///
+    /// While any is set, the lone modifier parameter of legacy sequences, e.g. the `9` in
+    /// `\x1b[1;9A`, is decoded with the kitty layout, where it means super rather than meta.
+    ///
+    /// ```rust
+    /// use etty::evt::{Event, InputParser, KeyCode, Mods, Nav};
+    ///
+    /// let mut kitty = InputParser::new();
+    /// kitty.kbd_enh(etty::KBD_ENH_DISAMBIGUATE);
+    /// kitty.feed(b"\x1b[1;9A");
+    /// let Event::Key(key) = kitty.next().unwrap().unwrap().event else { panic!() };
+    /// assert_eq!((key.code, key.mods), (KeyCode::Nav(Nav::Up), Mods::SUPER));
+    ///
+    /// let mut xterm = InputParser::new();
+    /// xterm.feed(b"\x1b[1;9A");
+    /// let Event::Key(key) = xterm.next().unwrap().unwrap().event else { panic!() };
+    /// assert_eq!((key.code, key.mods), (KeyCode::Nav(Nav::Up), Mods::META));
+    ///
+    /// xterm.feed(b"\x1b[?1u\x1b[1;9A");
+    /// let Event::Reply(_) = xterm.next().unwrap().unwrap().event else { panic!() };
+    /// let Event::Key(key) = xterm.next().unwrap().unwrap().event else { panic!() };
+    /// assert_eq!(key.mods, Mods::SUPER);
+    /// ```
+    pub fn kbd_enh(&mut self, flags: u8) -> &mut Self {
+        self.kbd_enh = flags;
+        self
+    }
+
+    /// Same with [`Iterator::next`] but stops waiting for the rest of the pending bytes, e.g. once the
+    /// input hits EOF or the escape timeout elapses.
+    ///
+    /// A lone `ESC` becomes the key itself, `ESC [` and `ESC O` become `Alt` plus `[` and `O`, and
+    /// other incomplete sequences are yielded as [`ParseErrorKind::Incomplete`].
+    pub fn flush(&mut self) -> Option<Result<EventAndRaw, ParseError>> {
+        self.parse(true)
+    }
+
+    fn parse(&mut self, eof: bool) -> Option<Result<EventAndRaw, ParseError>> {
+        // a long paste arrives in many reads, skip parsing it all over again until a byte which
+        // may end it comes in.
+        if let (false, Some(ends)) = (eof, pending_ends(&self.buf, self.replies)) {
+            let longest = ends.iter().map(|end| end.len()).max().unwrap_or(1);
+            let fresh = &self.buf[self.scanned.saturating_sub(longest - 1)..];
+            if !ends
+                .iter()
+                .any(|end| fresh.windows(end.len()).any(|w| w == *end))
+            {
+                self.scanned = self.buf.len();
+                return None;
             }
-            Ok(_) => unreachable!(),
-            Err(err) => Err(err),
+        }
+        let (&lead, rest) = self.buf.split_first()?;
+        let mut exhausted = false;
+        let res = {
+            let mut iter = rest
+                .iter()
+                .copied()
+                .chain(std::iter::from_fn(|| {
+                    exhausted = true;
+                    None
+                }))
+                .peekable();
+            parse(lead, &mut iter, self.replies, self.kbd_enh)
         };
+        // `scanned` is left alone, the possible end seen may be completed by the next bytes, e.g.
+        // the `\\` after an `ESC`.
+        if exhausted && !eof {
+            return None;
+        }
+        self.scanned = 0;
+        // every parse path puts the bytes it consumed into `raw`, anything it only looked at past
+        // them is parsed again.
+        let consumed = match &res {
+            Ok(event) => event.raw.len(),
+            Err(err) => err.raw.len(),
+        };
+        self.buf.drain(..consumed);
+        if let Ok(EventAndRaw {
+            event: Event::Reply(Reply::KbdEnh(flags)),
+            ..
+        }) = res
+        {
+            self.kbd_enh = flags;
+        }
         Some(res)
     }
 }
 
-fn parse<I>(lead: u8, iter: &mut I) -> std::io::Result<EventAndRaw>
+impl Iterator for InputParser {
+    type Item = Result<EventAndRaw, ParseError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.parse(false)
+    }
+}
+
+/// Input which doesn't form a valid sequence.
+///
+/// Parsing carries on with the bytes after [`ParseError::raw`], so an error never stalls the input.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub raw: Vec<u8>,
+}
+
+impl ParseError {
+    fn new(kind: ParseErrorKind, raw: Vec<u8>) -> Self {
+        Self { kind, raw }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ParseErrorKind {
+    InvalidUtf8,
+    /// A byte which doesn't belong to the sequence interrupted it.
+    Malformed,
+    /// The input ended in the middle of a sequence.
+    Incomplete,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            ParseErrorKind::InvalidUtf8 => "invalid utf-8",
+            ParseErrorKind::Malformed => "malformed sequence",
+            ParseErrorKind::Incomplete => "incomplete sequence",
+        };
+        write!(f, "{} {:?}", kind, self.raw)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for std::io::Error {
+    fn from(err: ParseError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, err)
+    }
+}
+
+type Parsed = Result<EventAndRaw, ParseError>;
+
+/// Bytes which may end the paste or string sequence `buf` starts with, if it does.
+fn pending_ends(buf: &[u8], replies: bool) -> Option<&'static [&'static [u8]]> {
+    match buf {
+        _ if buf.starts_with(PASTE_BEGIN) => Some(&[PASTE_END]),
+        [b'\x1b', b']', b, ..] if replies && b.is_ascii_digit() => Some(&[b"\x07", b"\x1b"]),
+        [b'\x1b', b'P', b, ..] if replies && (0x30..=0x3f).contains(b) => Some(&[b"\x1b"]),
+        _ => None,
+    }
+}
+
+fn parse<I>(lead: u8, iter: &mut std::iter::Peekable<I>, replies: bool, kbd_enh: u8) -> Parsed
 where
-    I: Iterator<Item = std::io::Result<u8>>,
+    I: Iterator<Item = u8>,
 {
     match lead {
-        b'\x1b' => parse_esc_seq(iter),
+        b'\x1b' => parse_esc_seq(iter, replies, kbd_enh),
         0..=26 | 28..=32 | 127 => {
-            let event = Event::Key(Key::C0(crate::C0::from(lead)));
+            let event = Event::Key(KeyCode::C0(crate::C0::from(lead)).into());
             Ok(EventAndRaw::new(event, vec![lead]))
         }
         _ => parse_utf8(lead, iter),
     }
 }
 
-fn parse_esc_seq<I>(iter: &mut I) -> std::io::Result<EventAndRaw>
+/// OSC and DCS strings are only parsed as such if `replies` are expected, so that e.g. `Alt+]`
+/// followed by `1` isn't taken for the start of one.
+fn parse_esc_seq<I>(iter: &mut std::iter::Peekable<I>, replies: bool, kbd_enh: u8) -> Parsed
 where
-    I: Iterator<Item = std::io::Result<u8>>,
+    I: Iterator<Item = u8>,
 {
-    let Some(Ok(next)) = iter.next() else {
-        unreachable!("the next byte should be `Some` as it has read before yanking it back to the `iter`");
+    let Some(next) = iter.next() else {
+        let event = Event::Key(KeyCode::C0(crate::C0::Esc).into());
+        return Ok(EventAndRaw::new(event, vec![b'\x1b']));
     };
+    let is_final = |b: &u8| (0x40..=0x7e).contains(b);
+    let is_csi = |b: &u8| (0x20..=0x7e).contains(b);
     match next {
-        b'[' => parse_csi(iter),
-        b'O' => {
+        b'[' if iter.peek().is_some_and(is_csi) => parse_csi(iter, kbd_enh),
+        b']' if replies && iter.peek().is_some_and(u8::is_ascii_digit) => parse_str_seq(next, iter),
+        b'P' if replies && iter.peek().is_some_and(|b| (0x30..=0x3f).contains(b)) => {
+            parse_str_seq(next, iter)
+        }
+        b'O' if iter.peek().is_some_and(is_final) => {
             let Some(b) = iter.next() else {
-                return Ok(EventAndRaw::new(Event::Undefined, vec![b'\x1b', b'O']));
+                return Err(ParseError::new(
+                    ParseErrorKind::Incomplete,
+                    vec![b'\x1b', b'O'],
+                ));
             };
-            let event = match b? {
-                b @ 80..=83 => {
-                    EventAndRaw::new(Event::Key(Key::F(b - b'O')), vec![b'\x1b', b'O', b])
-                } // F1, F2, F3, F4
+            let event = match b {
+                b @ 80..=83 => EventAndRaw::new(
+                    Event::Key(KeyCode::F(b - b'O').into()),
+                    vec![b'\x1b', b'O', b],
+                ), // F1, F2, F3, F4
                 b => EventAndRaw::new(Event::Undefined, vec![b'\x1b', b'O', b]),
             };
             Ok(event)
         }
-
+        0..=32 | 127 => {
+            let key = Key::new(KeyCode::C0(crate::C0::from(next)), Mods::ALT);
+            Ok(EventAndRaw::new(Event::Key(key), vec![b'\x1b', next]))
+        }
         _ => {
-            let (parse, raw) = {
-                // let EventAndRaw { event, raw } = parse(next, iter)?;
-
-                let EventAndRaw { event, raw } = parse_utf8(next, iter)?;
-                let Event::Key(Key::Utf8(c)) = event else {
-                    unreachable!();
-                };
-                let raw = [b'\x1b']
-                    .into_iter()
-                    .chain(raw.into_iter())
-                    .collect::<Vec<_>>();
-                (Event::Key(Key::Alt(c)), raw)
+            let with_esc = |raw: Vec<u8>| [b'\x1b'].into_iter().chain(raw).collect::<Vec<_>>();
+            let EventAndRaw { event, raw } = parse_utf8(next, iter).map_err(|err| ParseError {
+                raw: with_esc(err.raw),
+                ..err
+            })?;
+            let event = match event {
+                Event::Key(mut key) => {
+                    key.mods |= Mods::ALT;
+                    Event::Key(key)
+                }
+                _ => Event::Undefined,
             };
-            let event = EventAndRaw::new(parse, raw);
-            Ok(event)
+            Ok(EventAndRaw::new(event, with_esc(raw)))
         }
     }
 }
 
-fn parse_csi<I>(iter: &mut I) -> std::io::Result<EventAndRaw>
+/// Reads a CSI sequence up to and including its final byte.
+///
+/// A byte outside of the CSI grammar, e.g. the `ESC` of the next sequence, is left for the next
+/// event.
+///
+/// https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-PC-Style-Function-Keys
+fn parse_csi<I>(iter: &mut std::iter::Peekable<I>, kbd_enh: u8) -> Parsed
 where
-    I: Iterator<Item = std::io::Result<u8>>,
+    I: Iterator<Item = u8>,
 {
-    let Some(b) = iter.next() else {
-        return Ok(EventAndRaw::new(Event::Undefined, vec![b'\x1b', b'[']));
-    };
-
-    let event = match b {
-        Ok(digit @ b'1'..=b'9') => {
-            let mut buf = Vec::new();
+    let mut raw = vec![b'\x1b', b'['];
+    loop {
+        let Some(b) = iter.next_if(|b| (0x20..=0x7e).contains(b)) else {
+            let kind = match iter.peek() {
+                None => ParseErrorKind::Incomplete,
+                Some(_) => ParseErrorKind::Malformed,
+            };
+            return Err(ParseError::new(kind, raw));
+        };
+        match (raw.len(), b) {
+            (2, b'M') => return parse_mouse_x10(iter),
+            (2, b'<') => return parse_mouse_sgr(iter),
+            _ => raw.push(b),
+        }
+        if (0x40..=0x7e).contains(&b) {
+            break;
+        }
+    }
+    if raw == PASTE_BEGIN {
+        return parse_paste(iter, raw);
+    }
+    let (params, fin) = (&raw[2..raw.len() - 1], raw[raw.len() - 1]);
+    let event = csi_event(params, fin, kbd_enh)
+        .or_else(|| Reply::decode(&raw).map(Event::Reply))
+        .unwrap_or(Event::Undefined);
+    Ok(EventAndRaw::new(event, raw))
+}
 
-            loop {
-                let Some(res) = iter.next() else {
-                    let raw = [b'\x1b', b'[', digit].into_iter().chain(buf.into_iter()).collect::<Vec<u8>>();
-                    return Ok(EventAndRaw::new(Event::Undefined, raw));
-                };
-                let b = res?;
-                if b == b'~' {
+/// Reads an OSC or DCS string up to its `ST` terminator, or `BEL` for OSC.
+fn parse_str_seq<I>(intro: u8, iter: &mut std::iter::Peekable<I>) -> Parsed
+where
+    I: Iterator<Item = u8>,
+{
+    let mut raw = vec![b'\x1b', intro];
+    loop {
+        let Some(b) = iter.next() else {
+            return Err(ParseError::new(ParseErrorKind::Incomplete, raw));
+        };
+        match b {
+            b'\x07' if intro == b']' => {
+                raw.push(b);
+                break;
+            }
+            // the `ESC` and the byte after it are left out of `raw`, so they're parsed again
+            // as the next event.
+            b'\x1b' => match iter.next() {
+                Some(b'\\') => {
+                    raw.extend([b, b'\\']);
                     break;
                 }
-                buf.push(b);
-            }
+                None => return Err(ParseError::new(ParseErrorKind::Incomplete, raw)),
+                Some(_) => return Err(ParseError::new(ParseErrorKind::Malformed, raw)),
+            },
+            b => raw.push(b),
+        }
+    }
+    let event = Reply::decode(&raw)
+        .map(Event::Reply)
+        .unwrap_or(Event::Undefined);
+    Ok(EventAndRaw::new(event, raw))
+}
 
-            'not: {
-                if !buf.is_empty() {
-                    break 'not;
-                }
-                let nav = match digit {
-                    b'1' => Nav::Home,
-                    b'2' => Nav::Ins,
-                    b'3' => Nav::Del,
-                    b'4' => Nav::End,
-                    b'5' => Nav::PgUp,
-                    b'6' => Nav::PgDn,
-                    _ => break 'not,
-                };
-                return Ok(EventAndRaw::new(
-                    Event::Key(Key::Nav(nav)),
-                    vec![b'\x1b', b'[', digit],
-                ));
-            }
+const PASTE_BEGIN: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
 
-            'not: {
-                if buf.len() != 1 {
-                    break 'not;
-                }
-                let fnum = match buf[0] {
-                    b @ b'0'..=b'1' => b - 39, // F9, F10
-                    b @ b'4' => b - 40,        // F12
-                    b @ b'5' => b - 48,        // F5
-                    b @ b'7'..=b'9' => b - 49, // F6, F7, F8
-                    _ => break 'not,
-                };
-                let event = EventAndRaw::new(
-                    Event::Key(Key::F(fnum)),
-                    vec![b'\x1b', b'[', digit, buf[0], b'~'],
-                );
-                return Ok(event);
-            }
+/// Collects everything up to `\x1b[201~` into a single paste.
+///
+/// https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Bracketed-Paste-Mode
+fn parse_paste<I>(iter: &mut std::iter::Peekable<I>, mut raw: Vec<u8>) -> Parsed
+where
+    I: Iterator<Item = u8>,
+{
+    while !raw.ends_with(PASTE_END) {
+        let Some(b) = iter.next() else {
+            return Err(ParseError::new(ParseErrorKind::Incomplete, raw));
+        };
+        raw.push(b);
+    }
+    let text = &raw[PASTE_BEGIN.len()..raw.len() - PASTE_END.len()];
+    let event = Event::Paste(String::from_utf8_lossy(text).into_owned());
+    Ok(EventAndRaw::new(event, raw))
+}
 
-            let raw = [b'\x1b', b'[', digit]
-                .into_iter()
-                .chain(buf.into_iter())
-                .collect::<Vec<u8>>();
-            return Ok(EventAndRaw::new(Event::Undefined, raw));
+fn csi_event(params: &[u8], fin: u8, kbd_enh: u8) -> Option<Event> {
+    let params = csi_params(params)?;
+    if fin == b'u' {
+        return csi_kitty_key(&params).map(Event::Key);
+    }
+    let (lead, rest) = match params.split_first() {
+        Some((lead, rest)) => (lead.as_slice(), rest),
+        None => (&[][..], &[][..]),
+    };
+    let event = match (fin, lead) {
+        (b'I', []) if rest.is_empty() => Event::Window(Window::FocusIn),
+        (b'O', []) if rest.is_empty() => Event::Window(Window::FocusOut),
+        (b'~', [n]) => {
+            let code = match n {
+                1 | 7 => KeyCode::Nav(Nav::Home),
+                2 => KeyCode::Nav(Nav::Ins),
+                3 => KeyCode::Nav(Nav::Del),
+                4 | 8 => KeyCode::Nav(Nav::End),
+                5 => KeyCode::Nav(Nav::PgUp),
+                6 => KeyCode::Nav(Nav::PgDn),
+                11..=15 => KeyCode::F(*n as u8 - 10), // F1 ~ F5
+                17..=21 => KeyCode::F(*n as u8 - 11), // F6 ~ F10
+                23..=24 => KeyCode::F(*n as u8 - 12), // F11, F12
+                _ => return None,
+            };
+            Event::Key(csi_key(code, rest, kbd_enh)?)
         }
+        (_, [] | [1]) => {
+            let code = match fin {
+                b'A' => KeyCode::Nav(Nav::Up),
+                b'B' => KeyCode::Nav(Nav::Dn),
+                b'C' => KeyCode::Nav(Nav::Rgt),
+                b'D' => KeyCode::Nav(Nav::Lft),
+                b'F' => KeyCode::Nav(Nav::End),
+                b'H' => KeyCode::Nav(Nav::Home),
+                b'Z' => KeyCode::Nav(Nav::BTab),
+                b'P'..=b'S' => KeyCode::F(fin - b'O'), // F1, F2, F3, F4
+                _ => return None,
+            };
+            Event::Key(csi_key(code, rest, kbd_enh)?)
+        }
+        _ => return None,
+    };
+    Some(event)
+}
+
+/// Splits CSI parameters into `;` separated fields of `:` separated sub-parameters.
+///
+/// Empty sub-parameters are `0`. Returns `None` if any of them is not a number.
+fn csi_params(bytes: &[u8]) -> Option<Vec<Vec<u32>>> {
+    if bytes.is_empty() {
+        return Some(Vec::new());
+    }
+    bytes
+        .split(|b| *b == b';')
+        .map(|field| {
+            field
+                .split(|b| *b == b':')
+                .map(|bytes| match bytes.is_empty() {
+                    true => Some(0),
+                    false => crate::bytes_to_uint::<u32>(bytes),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Builds a key out of the `mods[:event]` field trailing legacy key sequences.
+///
+/// Only the kitty protocol reports an event type, so its presence selects the modifier encoding,
+/// otherwise it's the one of the protocol in effect, see [`InputParser::kbd_enh`].
+fn csi_key(code: KeyCode, params: &[Vec<u32>], kbd_enh: u8) -> Option<Key> {
+    let mut key = Key::new(code, Mods::NONE);
+    match params {
+        [] => {}
+        [field] => match field.as_slice() {
+            [mods] if kbd_enh != 0 => key.mods = Mods::from_kitty(*mods),
+            [mods] => key.mods = Mods::from_xterm(*mods)?,
+            [mods, kind] => {
+                key.mods = Mods::from_kitty(*mods);
+                key.kind = kitty_kind(*kind)?;
+            }
+            _ => return None,
+        },
+        _ => return None,
+    }
+    Some(key)
+}
 
-        Ok(b @ b'A') => EventAndRaw::new(Event::Key(Key::Nav(Nav::Up)), vec![b'\x1b', b'[', b]),
-        Ok(b @ b'B') => EventAndRaw::new(Event::Key(Key::Nav(Nav::Dn)), vec![b'\x1b', b'[', b]),
-        Ok(b @ b'C') => EventAndRaw::new(Event::Key(Key::Nav(Nav::Rgt)), vec![b'\x1b', b'[', b]),
-        Ok(b @ b'D') => EventAndRaw::new(Event::Key(Key::Nav(Nav::Lft)), vec![b'\x1b', b'[', b]),
-        Ok(b @ b'F') => EventAndRaw::new(Event::Key(Key::Nav(Nav::End)), vec![b'\x1b', b'[', b]),
-        Ok(b @ b'H') => EventAndRaw::new(Event::Key(Key::Nav(Nav::Home)), vec![b'\x1b', b'[', b]),
-        Ok(b @ b'Z') => EventAndRaw::new(Event::Key(Key::Nav(Nav::BTab)), vec![b'\x1b', b'[', b]),
-        Ok(b @ b'I') => EventAndRaw::new(Event::Window(Window::FocusIn), vec![b'\x1b', b'[', b]),
-        Ok(b @ b'O') => EventAndRaw::new(Event::Window(Window::FocusOut), vec![b'\x1b', b'[', b]),
-
-        Ok(b'M') => parse_mouse_x10(iter),
-        Ok(b'<') => parse_mouse_sgr(iter),
-        Ok(b) => EventAndRaw::new(Event::Undefined, vec![b'\x1b', b'[', b]),
-        Err(err) => return Err(err),
+/// Decodes `CSI code[:shifted[:base]] ; mods[:event] ; text u`.
+///
+/// https://sw.kovidgoyal.net/kitty/keyboard-protocol/#an-overview
+fn csi_kitty_key(params: &[Vec<u32>]) -> Option<Key> {
+    let (code, rest) = params.split_first()?;
+    if rest.len() > 2 {
+        return None;
+    }
+    let code = match *code.first()? {
+        cp @ (0..=31 | 127) => KeyCode::C0(crate::C0::from(cp as u8)),
+        cp @ 57376..=57398 => KeyCode::F((cp - 57376 + 13) as u8), // F13 ~ F35
+        cp @ 57344..=63743 => KeyCode::Kitty(cp),
+        cp => KeyCode::Utf8(char::from_u32(cp)?),
+    };
+    let mut key = Key::new(code, Mods::NONE);
+    match rest.first().map(Vec::as_slice) {
+        None | Some([]) => {}
+        Some([mods]) => key.mods = Mods::from_kitty(*mods),
+        Some([mods, kind]) => {
+            key.mods = Mods::from_kitty(*mods);
+            key.kind = kitty_kind(*kind)?;
+        }
+        Some(_) => return None,
+    }
+    key.text = match rest.get(1) {
+        Some(cps) => Some(
+            cps.iter()
+                .map(|cp| char::from_u32(*cp))
+                .collect::<Option<_>>()?,
+        ),
+        None => None,
     };
-    Ok(event)
+    Some(key)
 }
 
-fn parse_utf8<I>(lead: u8, iter: &mut I) -> std::io::Result<EventAndRaw>
+fn kitty_kind(param: u32) -> Option<KeyKind> {
+    match param {
+        0 | 1 => Some(KeyKind::Press),
+        2 => Some(KeyKind::Repeat),
+        3 => Some(KeyKind::Release),
+        _ => None,
+    }
+}
+
+fn parse_utf8<I>(lead: u8, iter: &mut std::iter::Peekable<I>) -> Parsed
 where
-    I: Iterator<Item = std::io::Result<u8>>,
+    I: Iterator<Item = u8>,
 {
-    if lead.is_ascii() {
-        let event = EventAndRaw::new(Event::Key(Key::Utf8(lead as char)), vec![lead]);
-        return Ok(event);
-    }
-    let mut buf = {
-        let mut v = Vec::with_capacity(4);
-        v.push(lead);
-        v
+    let len = match lead {
+        0..=0x7f => 1,
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => return Err(ParseError::new(ParseErrorKind::InvalidUtf8, vec![lead])),
     };
-    #[allow(clippy::never_loop)] // it does loop idk why clippy yell at it.
-    for next_b in iter.by_ref() {
-        buf.push(next_b?);
-        let Ok(s) = std::str::from_utf8(buf.as_slice()) else {
-            if buf.len() <= 4 {
-                continue;
-            } else {
-                break;
-            }
-        };
-        let parse = {
-            let mut chars = s.chars();
-            let c = chars.next().unwrap();
-            assert!(chars.next().is_none());
-            Event::Key(Key::Utf8(c))
+    let mut raw = Vec::with_capacity(len);
+    raw.push(lead);
+    while raw.len() < len {
+        let Some(b) = iter.next_if(|b| (0x80..=0xbf).contains(b)) else {
+            let kind = match iter.peek() {
+                None => ParseErrorKind::Incomplete,
+                Some(_) => ParseErrorKind::InvalidUtf8,
+            };
+            return Err(ParseError::new(kind, raw));
         };
-        buf.shrink_to_fit();
-        let event = EventAndRaw::new(parse, buf);
-        return Ok(event);
+        raw.push(b);
     }
-    let event = EventAndRaw::new(Event::Undefined, buf);
-    Ok(event)
+    let Some(c) = std::str::from_utf8(&raw)
+        .ok()
+        .and_then(|s| s.chars().next())
+    else {
+        return Err(ParseError::new(ParseErrorKind::InvalidUtf8, raw));
+    };
+    Ok(EventAndRaw::new(Event::Key(KeyCode::Utf8(c).into()), raw))
 }
 
-fn parse_mouse_x10<I>(iter: &mut I) -> EventAndRaw
+fn parse_mouse_x10<I>(iter: &mut std::iter::Peekable<I>) -> Parsed
 where
-    I: Iterator<Item = std::io::Result<u8>>,
+    I: Iterator<Item = u8>,
 {
-    let mut raw = {
-        let mut v = Vec::with_capacity(6);
-        v.push(b'\x1b');
-        v.push(b'[');
-        v.push(b'M');
-        v
-    };
-    let mut next = || {
-        let b = iter.next().unwrap().unwrap();
+    let mut raw = vec![b'\x1b', b'[', b'M'];
+    for _ in 0..3 {
+        let Some(b) = iter.next() else {
+            return Err(ParseError::new(ParseErrorKind::Incomplete, raw));
+        };
         raw.push(b);
-        b.saturating_sub(32_u8) as u16
-    };
-    let (cb, cx, cy) = (next(), next(), next());
-    let mouse = match cb {
-        0 => Mouse::PrssLft(cx, cy),
-        1 => Mouse::PrssMid(cx, cy),
-        2 => Mouse::PrssRgt(cx, cy),
-
-        3 => Mouse::RlseBtn(cx, cy),
-
-        8 => Mouse::RlseLftAlt(cx, cy),
-        9 => Mouse::RlseMidAlt(cx, cy),
-        10 => Mouse::RlseRgtAlt(cx, cy),
-
-        16 => Mouse::RlseLftCtrl(cx, cy),
-        17 => Mouse::RlseMidCtrl(cx, cy),
-        18 => Mouse::RlseRgtCtrl(cx, cy),
-
-        32 => Mouse::DragLft(cx, cy),
-        33 => Mouse::DragMid(cx, cy),
-        34 => Mouse::DragRgt(cx, cy),
-
-        40 => Mouse::DragLftAlt(cx, cy),
-        41 => Mouse::DragMidAlt(cx, cy),
-        42 => Mouse::DragRgtAlt(cx, cy),
-
-        48 => Mouse::DragLftCtrl(cx, cy),
-        49 => Mouse::DragMidCtrl(cx, cy),
-        50 => Mouse::DragRgtCtrl(cx, cy),
-
-        64 => Mouse::WheelDn(cx, cy),
-        65 => Mouse::WheelUp(cx, cy),
-
-        70 => Mouse::WheelDnShift(cx, cy),
-        71 => Mouse::WheelUpShift(cx, cy),
-
-        72 => Mouse::WheelDnAlt(cx, cy),
-        73 => Mouse::WheelUpAlt(cx, cy),
-
-        80 => Mouse::WheelDnCtrl(cx, cy),
-        81 => Mouse::WheelUpCtrl(cx, cy),
-
-        _ => Mouse::UNIMPLEMENTED(cb, cx, cy),
-    };
-    let parse = Event::Mouse(mouse);
-    EventAndRaw::new(parse, raw)
+    }
+    let [cb, cx, cy] = [raw[3], raw[4], raw[5]].map(|b| b.saturating_sub(32) as u16);
+    let event = Event::Mouse(Mouse::decode(cb, cx, cy, false));
+    Ok(EventAndRaw::new(event, raw))
 }
 
 /// https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h3-Extended-coordinates
-fn parse_mouse_sgr<I>(iter: &mut I) -> EventAndRaw
+fn parse_mouse_sgr<I>(iter: &mut std::iter::Peekable<I>) -> Parsed
 where
-    I: Iterator<Item = std::io::Result<u8>>,
+    I: Iterator<Item = u8>,
 {
-    // static OFF_SET: u8 = 48;
-    let mut raw = Vec::with_capacity(16);
-    let (b_m, bytes) = {
-        let mut m: Option<u8> = None;
-        let bytes = iter
-            .map_while(|b| {
-                //
-                match b.unwrap() {
-                    b_m @ (b'm' | b'M') => {
-                        m = Some(b_m);
-                        None
-                    }
-                    b => Some(b),
-                }
-            })
-            .collect::<Vec<u8>>();
-        (m.unwrap(), bytes)
+    let mut raw = vec![b'\x1b', b'[', b'<'];
+    let fin = loop {
+        let Some(b) = iter.next_if(|b| matches!(b, b'0'..=b'9' | b';' | b'm' | b'M')) else {
+            let kind = match iter.peek() {
+                None => ParseErrorKind::Incomplete,
+                Some(_) => ParseErrorKind::Malformed,
+            };
+            return Err(ParseError::new(kind, raw));
+        };
+        raw.push(b);
+        if let b'm' | b'M' = b {
+            break b;
+        }
     };
-    raw.push(b_m);
-
-    let mut vals = bytes.rsplit(|b| *b == b';').map(|bytes| {
-        let num = crate::bytes_to_uint::<u16>(bytes).unwrap();
-        raw.push(b';');
-        num
-    });
-
-    let (cy, cx, cb) = (
-        vals.next().unwrap(),
-        vals.next().unwrap(),
-        vals.next().unwrap(),
-    );
-    assert!(vals.next().is_none());
-
-    let parse = {
-        let mouse = match (cb, b_m) {
-            (35, b'M') => Mouse::Motion(cx, cy),
-
-            (0, b'm') => Mouse::RlseLft(cx, cy),
-            (1, b'm') => Mouse::RlseMid(cx, cy),
-            (2, b'm') => Mouse::RlseRgt(cx, cy),
-
-            (8, b'm') => Mouse::RlseLftAlt(cx, cy),
-            (9, b'm') => Mouse::RlseMidAlt(cx, cy),
-            (10, b'm') => Mouse::RlseRgtAlt(cx, cy),
-
-            (16, b'm') => Mouse::RlseLftCtrl(cx, cy),
-            (17, b'm') => Mouse::RlseMidCtrl(cx, cy),
-            (18, b'm') => Mouse::RlseRgtCtrl(cx, cy),
+    let params = csi_params(&raw[3..raw.len() - 1]).unwrap_or_default();
+    let event = match params.as_slice() {
+        [cb, cx, cy] => match (cb.as_slice(), cx.as_slice(), cy.as_slice()) {
+            ([cb], [cx], [cy]) => {
+                let [cb, cx, cy] = [*cb, *cx, *cy].map(|n| n.min(u16::MAX as u32) as u16);
+                Event::Mouse(Mouse::decode(cb, cx, cy, fin == b'm'))
+            }
+            _ => Event::Undefined,
+        },
+        _ => Event::Undefined,
+    };
+    Ok(EventAndRaw::new(event, raw))
+}
 
-            (0, b'M') => Mouse::PrssLft(cx, cy),
-            (1, b'M') => Mouse::PrssMid(cx, cy),
-            (2, b'M') => Mouse::PrssRgt(cx, cy),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::time::Duration;
+
+    /// Stream reading the returned pipe instead of a terminal.
+    fn pipe_stream() -> (EventAndRawStream, std::fs::File) {
+        let (rx, tx) = crate::unix::pipe().unwrap();
+        let events = event_and_raw_stream(Ok(rx), Duration::from_millis(25), false, Some(0));
+        (events, tx)
+    }
 
-            (8, b'M') => Mouse::PrssLftAlt(cx, cy),
-            (9, b'M') => Mouse::PrssMidAlt(cx, cy),
-            (10, b'M') => Mouse::PrssRgtAlt(cx, cy),
+    #[test]
+    fn pending_paste_is_scanned_once() {
+        let mut parser = InputParser::new();
+        parser.feed(PASTE_BEGIN);
+        assert!(parser.next().is_none());
+        for _ in 0..100 {
+            let seen = parser.scanned;
+            parser.feed(b"0123456789");
+            assert!(parser.next().is_none());
+            // nothing before the bytes just fed was scanned again, and they all were.
+            assert_eq!(
+                (seen, parser.scanned),
+                (parser.buf.len() - 10, parser.buf.len())
+            );
+        }
+        parser.feed(b"\x1b[20");
+        assert!(parser.next().is_none());
+        parser.feed(b"1~");
+        let Some(Ok(EventAndRaw {
+            event: Event::Paste(text),
+            ..
+        })) = parser.next()
+        else {
+            panic!()
+        };
+        assert_eq!(text.len(), 1000);
+        assert_eq!((parser.scanned, parser.buf.len()), (0, 0));
+    }
 
-            (16, b'M') => Mouse::PrssLftCtrl(cx, cy),
-            (17, b'M') => Mouse::PrssMidCtrl(cx, cy),
-            (18, b'M') => Mouse::PrssRgtCtrl(cx, cy),
+    #[test]
+    fn split_string_terminator_is_found() {
+        let mut parser = InputParser::new();
+        parser.expect_replies(true);
+        parser.feed(b"\x1b]11;rgb:0/0/0");
+        assert!(parser.next().is_none());
+        parser.feed(b"\x1b");
+        assert!(parser.next().is_none());
+        parser.feed(b"\\");
+        let event = parser.next().unwrap().unwrap().event;
+        assert!(matches!(event, Event::Reply(Reply::BgColor(0, 0, 0))));
+    }
 
-            (32, b'M') => Mouse::DragLft(cx, cy),
-            (33, b'M') => Mouse::DragMid(cx, cy),
-            (34, b'M') => Mouse::DragRgt(cx, cy),
+    #[test]
+    fn malformed_input_does_not_end_the_stream() {
+        let (mut events, mut tx) = pipe_stream();
+        tx.write_all(b"\xff\x1b[1\x1b[Aa").unwrap();
+        let mut next = || {
+            events
+                .next_timeout(Duration::from_secs(1))
+                .unwrap()
+                .unwrap()
+        };
 
-            (40, b'M') => Mouse::DragLftAlt(cx, cy),
-            (41, b'M') => Mouse::DragMidAlt(cx, cy),
-            (42, b'M') => Mouse::DragLftAlt(cx, cy),
+        let event = next();
+        assert!(matches!(event.event, Event::Undefined));
+        assert_eq!(event.raw, b"\xff");
+        let event = next();
+        assert!(matches!(event.event, Event::Undefined));
+        assert_eq!(event.raw, b"\x1b[1");
+        let Event::Key(key) = next().event else {
+            panic!()
+        };
+        assert_eq!(key.code, KeyCode::Nav(Nav::Up));
+        let Event::Key(key) = next().event else {
+            panic!()
+        };
+        assert_eq!(key.code, KeyCode::Utf8('a'));
+    }
 
-            (48, b'M') => Mouse::DragLftCtrl(cx, cy),
-            (49, b'M') => Mouse::DragMidCtrl(cx, cy),
-            (50, b'M') => Mouse::DragLftCtrl(cx, cy),
+    #[test]
+    fn split_paste_waits_past_the_esc_timeout() {
+        let (mut events, mut tx) = pipe_stream();
+        tx.write_all(b"\x1b[200~hello ").unwrap();
+        std::thread::sleep(Duration::from_millis(60));
+        tx.write_all(b"q world\x1b[201~").unwrap();
+        let event = events
+            .next_timeout(Duration::from_secs(1))
+            .unwrap()
+            .unwrap();
+        let Event::Paste(text) = event.event else {
+            panic!("{:?}", event)
+        };
+        assert_eq!(text, "hello q world");
+    }
 
-            (64, b'M') => Mouse::WheelDn(cx, cy),
-            (65, b'M') => Mouse::WheelUp(cx, cy),
+    #[test]
+    fn lone_esc_is_flushed_after_the_esc_timeout() {
+        let (mut events, mut tx) = pipe_stream();
+        tx.write_all(b"\x1b").unwrap();
+        let event = events
+            .next_timeout(Duration::from_secs(1))
+            .unwrap()
+            .unwrap();
+        let Event::Key(key) = event.event else {
+            panic!("{:?}", event)
+        };
+        assert_eq!(
+            (key.code, key.mods),
+            (KeyCode::C0(crate::C0::Esc), Mods::NONE)
+        );
+    }
 
-            (70, b'M') => Mouse::WheelDnShift(cx, cy),
-            (71, b'M') => Mouse::WheelUpShift(cx, cy),
+    #[test]
+    fn close_and_drop_join_the_reader_thread() {
+        let (events, mut tx) = pipe_stream();
+        events.close();
+        // the read end is dropped along with the thread, before `close` returns.
+        let err = tx.write_all(b"a").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+
+        let (events, mut tx) = pipe_stream();
+        drop(events);
+        let err = tx.write_all(b"a").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+    }
 
-            (72, b'M') => Mouse::WheelDnAlt(cx, cy),
-            (73, b'M') => Mouse::WheelUpAlt(cx, cy),
+    #[test]
+    fn idle_stream_times_out() {
+        let (mut events, _tx) = pipe_stream();
+        assert!(!events.poll(Duration::from_millis(20)).unwrap());
+        assert!(events.try_next().unwrap().is_none());
+        assert!(events
+            .next_timeout(Duration::from_millis(20))
+            .unwrap()
+            .is_none());
+    }
 
-            (80, b'M') => Mouse::WheelDnCtrl(cx, cy),
-            (81, b'M') => Mouse::WheelUpCtrl(cx, cy),
+    #[test]
+    fn closed_input_ends_the_stream() {
+        let (mut events, tx) = pipe_stream();
+        drop(tx);
+        let eof = std::io::ErrorKind::UnexpectedEof;
+        let err = events.next_timeout(Duration::from_secs(1)).unwrap_err();
+        assert_eq!(err.kind(), eof);
+        assert_eq!(events.poll(Duration::from_secs(1)).unwrap_err().kind(), eof);
+        assert_eq!(events.try_next().unwrap_err().kind(), eof);
+        assert!(events.next().is_none());
+    }
 
-            _ => Mouse::UNIMPLEMENTED(cb, cx, cy),
-        };
-        Event::Mouse(mouse)
-    };
-    raw.push(b'<');
-    raw.push(b'[');
-    raw.push(b'\x1b');
-    raw.shrink_to_fit();
-    raw.reverse();
-    EventAndRaw::new(parse, raw)
+    #[test]
+    fn async_stream_leaves_the_given_tty_blocking() {
+        use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+        let (mut master, mut slave) = (-1, -1);
+        let (name, termp, winp) = (std::ptr::null_mut(), std::ptr::null(), std::ptr::null());
+        let res = unsafe { libc::openpty(&mut master, &mut slave, name, termp, winp) };
+        assert_eq!(res, 0);
+        let _master = unsafe { OwnedFd::from_raw_fd(master) };
+        let tty = crate::term::Tty::from(unsafe { OwnedFd::from_raw_fd(slave) });
+        let nonblocking =
+            || unsafe { libc::fcntl(tty.as_raw_fd(), libc::F_GETFL) } & libc::O_NONBLOCK;
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let _rt = rt.enter();
+        let events = stream_builder().tty(&tty).init_async().unwrap();
+        assert_eq!(nonblocking(), 0);
+        drop(events);
+        assert_eq!(nonblocking(), 0);
+    }
 }
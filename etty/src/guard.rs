@@ -0,0 +1,481 @@
+//! Terminal restoration on panics and fatal signals.
+//!
+//! [`TermMode`](crate::term::TermMode) restores the terminal when it's dropped, which never
+//! happens with `panic = "abort"` nor when the process is killed by a signal. [`install`] covers
+//! those, so the shell isn't left in raw mode with the alternate buffer and mouse tracking on.
+//!
+//! ```rust,no_run
+//! use etty::guard::Modes;
+//!
+//! etty::guard::install()?;
+//! let _raw = etty::raw_mode();
+//! etty::guard::modes_set(Modes::ALT_BUF | Modes::MOUSE)?;
+//! panic!("the terminal is restored before this message is printed");
+//! # Ok::<(), etty::Error>(())
+//! ```
+//!
+//! It also keeps track of the [`Modes`] turned on with [`modes_set`], so [`restore`] only turns
+//! off the ones which are on, and they can be turned back on after the process is
+//! [suspended](suspend).
+
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU16;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use crate::term::Tty;
+use crate::unix::Termios;
+
+struct Saved {
+    tty: Tty,
+    termios: Termios,
+}
+
+static SAVED: once_cell::sync::OnceCell<Saved> = once_cell::sync::OnceCell::new();
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+// built beforehand, signal handlers must not allocate.
+struct Reset {
+    // in the order of `Modes::csi`.
+    modes: Vec<(Modes, String)>,
+    sgr: String,
+    kbd_enh: String,
+}
+
+static RESET: once_cell::sync::OnceCell<Reset> = once_cell::sync::OnceCell::new();
+
+fn reset() -> &'static Reset {
+    RESET.get_or_init(|| Reset {
+        modes: Modes::ORDER
+            .iter()
+            .map(|(mode, _, rst)| (*mode, rst().to_string()))
+            .collect(),
+        sgr: crate::csi::sgr_rst().to_string(),
+        kbd_enh: crate::csi::evt_kbd_enh_pop(1).to_string(),
+    })
+}
+
+/// Turns off the [`active_modes`] in reverse order, pops the [`kbd_enh`] flags pushed, and
+/// resets the style. Async-signal-safe.
+fn write_reset(fd: std::os::unix::io::RawFd, reset: &Reset) {
+    let write = |csi: &str| unsafe {
+        libc::write(fd, csi.as_ptr() as *const libc::c_void, csi.len());
+    };
+    let active = active_modes();
+    reset
+        .modes
+        .iter()
+        .rev()
+        .filter(|(mode, _)| active.contains(*mode))
+        .for_each(|(_, csi)| write(csi));
+    for _ in 0..KBD_ENH_DEPTH.load(Ordering::SeqCst) {
+        write(&reset.kbd_enh);
+    }
+    write(&reset.sgr);
+}
+
+/// Installs a panic hook and handlers of `SIGTERM`, `SIGINT`, `SIGHUP` and `SIGQUIT` which
+/// [`restore`] the terminal. Calling it again does nothing.
+///
+/// It saves the settings to restore: the ones the oldest live [`TermMode`](crate::term::TermMode)
+/// started from, or else the ones in effect. So it may be called after entering raw mode, as long
+/// as raw mode was entered with a `TermMode`.
+///
+/// The panic hook restores the terminal before running the previous hook, so the panic message
+/// isn't garbled. It does so on a panic in any thread, even one which is caught or only ends
+/// its thread, after which the terminal stays restored.
+///
+/// The signal handlers restore it, put the disposition they replaced back and raise the signal
+/// again. So the process still terminates by default, and a handler installed before, e.g. for a
+/// graceful shutdown, still runs. Signals which are ignored are left alone.
+pub fn install() -> crate::Result<()> {
+    if SAVED.get().is_none() {
+        let tty = Tty::open()?;
+        let termios = match ORIGINS.lock().first() {
+            Some((_, termios)) => termios.clone(),
+            None => crate::unix::get_term_attr(tty.as_raw_fd())?,
+        };
+        let _ = SAVED.set(Saved { tty, termios });
+    }
+    reset();
+    if INSTALLED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    let prev = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore();
+        prev(info);
+    }));
+    for (sig, prev) in FATAL.into_iter().zip(&PREV_FATAL) {
+        let action = crate::signal::get_handler(sig)?;
+        if action.sa_sigaction == libc::SIG_IGN {
+            continue;
+        }
+        let _ = prev.set(action);
+        crate::signal::set_handler(sig, on_fatal, 0)?;
+    }
+    Ok(())
+}
+
+const FATAL: [libc::c_int; 4] = [libc::SIGTERM, libc::SIGINT, libc::SIGHUP, libc::SIGQUIT];
+// dispositions replaced by `on_fatal`, in the order of `FATAL`.
+static PREV_FATAL: [once_cell::sync::OnceCell<libc::sigaction>; 4] =
+    [const { once_cell::sync::OnceCell::new() }; 4];
+
+/// Restores the terminal right away: turns the [`active_modes`] off, e.g. back to the main
+/// buffer, pops the kitty keyboard flags, and sets the settings saved by [`install`].
+///
+/// Async-signal-safe, does nothing unless [`install`] was called.
+pub fn restore() {
+    let (Some(saved), Some(reset)) = (SAVED.get(), RESET.get()) else {
+        return;
+    };
+    let fd = saved.tty.as_raw_fd();
+    write_reset(fd, reset);
+    let _ = saved.termios.set_attr(fd);
+}
+
+extern "C" fn on_fatal(sig: libc::c_int) {
+    let errno = unsafe { *crate::signal::errno_location() };
+    restore();
+    let prev = FATAL
+        .iter()
+        .position(|fatal| *fatal == sig)
+        .and_then(|i| PREV_FATAL[i].get());
+    // the signal is blocked until this returns, it's delivered to the previous disposition then.
+    unsafe {
+        match prev {
+            Some(prev) => {
+                libc::sigaction(sig, prev, std::ptr::null_mut());
+            }
+            None => {
+                libc::signal(sig, libc::SIG_DFL);
+            }
+        }
+        libc::raise(sig);
+        *crate::signal::errno_location() = errno;
+    }
+}
+
+// settings the live `TermMode`s started from, the oldest first.
+static ORIGINS: parking_lot::Mutex<Vec<(u64, Termios)>> = parking_lot::const_mutex(Vec::new());
+static NEXT_ORIGIN: AtomicU64 = AtomicU64::new(0);
+
+/// Records the settings a `TermMode` started from, until it [`leave`]s.
+pub(crate) fn enter(termios: &Termios) -> u64 {
+    let id = NEXT_ORIGIN.fetch_add(1, Ordering::Relaxed);
+    ORIGINS.lock().push((id, termios.clone()));
+    id
+}
+
+pub(crate) fn leave(id: u64) {
+    ORIGINS.lock().retain(|(other, _)| *other != id);
+}
+
+/// Settings from before the program took the terminal over: the ones the oldest live `TermMode`
+/// started from, or else the ones saved by [`install`].
+fn origin() -> Option<Termios> {
+    match ORIGINS.lock().first() {
+        Some((_, termios)) => Some(termios.clone()),
+        None => SAVED.get().map(|saved| saved.termios.clone()),
+    }
+}
+
+/// Same with [`restore`] but with the settings in effect when the program took the terminal
+/// over, and regardless of [`install`].
+fn release(tty: &Tty) -> crate::Result<()> {
+    let _ = std::io::stdout().flush();
+    write_reset(tty.as_raw_fd(), reset());
+    if let Some(termios) = origin() {
+        termios.set_attr(tty.as_raw_fd())?;
+    }
+    Ok(())
+}
+
+/// Stops the process like `Ctrl-Z` in a shell would, with the terminal restored in the meantime.
+///
+/// Returns once the process is continued, e.g. by `fg`, with the terminal settings and the
+/// [`active_modes`] back as they were. Event streams yield
+/// [`Event::Resumed`](crate::evt::Event::Resumed) then, so the screen can be repainted.
+///
+/// Streams built with [`job_control`](crate::evt::StreamBuilder::job_control) call it on `Ctrl-Z`
+/// and `SIGTSTP` by themselves.
+pub fn suspend() -> crate::Result<()> {
+    let mut tty = Tty::open()?;
+    let termios = crate::unix::get_term_attr(tty.as_raw_fd())?;
+    release(&tty)?;
+    let cont = crate::signal::SigPipe::new(libc::SIGCONT)?;
+    // the whole process group, same as `SIGTSTP` from the terminal.
+    unsafe { libc::kill(0, libc::SIGSTOP) };
+    // the calling thread might carry on for a bit before the group stop reaches it.
+    cont.wait()?;
+    reclaim(&mut tty, &termios)
+}
+
+/// Hands the terminal over to `f`, e.g. to run `$EDITOR` or a pager, and takes it back after.
+///
+/// Event streams stop reading the input in the meantime, and the terminal is handed over the same
+/// way as by [`suspend`]. The terminal settings, the [`active_modes`] and the kitty keyboard flags
+/// are set back afterwards, and input left over by `f`, e.g. keys typed while the child exited,
+/// is discarded.
+///
+/// Fails with [`TimedOut`](std::io::ErrorKind::TimedOut), without running `f`, if an event stream
+/// doesn't stop reading within a second.
+///
+/// ```rust,no_run
+/// let _raw = etty::raw_mode();
+/// let events = etty::event_stream();
+/// let status = etty::guard::with_suspended_terminal(|| {
+///     std::process::Command::new("vi").arg("notes.txt").status()
+/// })?;
+/// # Ok::<(), etty::Error>(())
+/// ```
+pub fn with_suspended_terminal<T>(f: impl FnOnce() -> T) -> crate::Result<T> {
+    let mut tty = Tty::open()?;
+    let termios = crate::unix::get_term_attr(tty.as_raw_fd())?;
+    let _pause = crate::query::Pause::new(std::time::Duration::from_secs(1))?;
+    release(&tty)?;
+    let res = f();
+    reclaim(&mut tty, &termios)?;
+    unsafe { libc::tcflush(tty.as_raw_fd(), libc::TCIFLUSH) };
+    Ok(res)
+}
+
+/// Takes the terminal back after [`release`], with the settings in effect before it.
+fn reclaim(tty: &mut Tty, termios: &Termios) -> crate::Result<()> {
+    termios.set_attr(tty.as_raw_fd())?;
+    out_modes(tty, active_modes(), true)?;
+    for flags in KBD_ENH.lock().iter() {
+        write!(tty, "{}", crate::csi::evt_kbd_enh_push(*flags))?;
+    }
+    tty.flush()?;
+    Ok(())
+}
+
+/// Bitset of the terminal modes tracked by [`modes_set`] and [`modes_rst`], see [`active_modes`].
+///
+/// ```rust
+/// use etty::guard::Modes;
+///
+/// let modes = Modes::ALT_BUF | Modes::MOUSE;
+/// assert!(modes.contains(Modes::MOUSE));
+/// assert!(!modes.contains(Modes::WIN_FOCUS));
+/// ```
+#[derive(Debug, Default, Eq, PartialEq, Hash, Clone, Copy)]
+pub struct Modes(u16);
+
+type Builder = fn() -> crate::csi::Csi<'static>;
+
+impl Modes {
+    pub const NONE: Modes = Modes(0);
+    /// [`alt_buf_set`](crate::csi::alt_buf_set).
+    pub const ALT_BUF: Modes = Modes(1);
+    /// [`cus_hide`](crate::csi::cus_hide).
+    pub const CUS_HIDE: Modes = Modes(1 << 1);
+    /// [`evt_mouse_set`](crate::csi::evt_mouse_set).
+    pub const MOUSE: Modes = Modes(1 << 2);
+    /// [`evt_mouse_drag_set`](crate::csi::evt_mouse_drag_set).
+    pub const MOUSE_DRAG: Modes = Modes(1 << 3);
+    /// [`evt_mouse_motion_set`](crate::csi::evt_mouse_motion_set).
+    pub const MOUSE_MOTION: Modes = Modes(1 << 4);
+    /// [`evt_mouse_ext_set`](crate::csi::evt_mouse_ext_set).
+    pub const MOUSE_EXT: Modes = Modes(1 << 5);
+    /// [`evt_win_focus_set`](crate::csi::evt_win_focus_set).
+    pub const WIN_FOCUS: Modes = Modes(1 << 6);
+    /// [`evt_paste_set`](crate::csi::evt_paste_set).
+    pub const PASTE: Modes = Modes(1 << 7);
+
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+    pub const fn contains(self, other: Modes) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub(crate) const fn without(self, other: Modes) -> Modes {
+        Modes(self.0 & !other.0)
+    }
+
+    /// Modes along with the builders turning them on and off, in the order they're turned on.
+    const ORDER: [(Modes, Builder, Builder); 8] = [
+        (
+            Modes::ALT_BUF,
+            crate::csi::alt_buf_set,
+            crate::csi::alt_buf_rst,
+        ),
+        (Modes::CUS_HIDE, crate::csi::cus_hide, crate::csi::cus_show),
+        (
+            Modes::MOUSE,
+            crate::csi::evt_mouse_set,
+            crate::csi::evt_mouse_rst,
+        ),
+        (
+            Modes::MOUSE_DRAG,
+            crate::csi::evt_mouse_drag_set,
+            crate::csi::evt_mouse_drag_rst,
+        ),
+        (
+            Modes::MOUSE_MOTION,
+            crate::csi::evt_mouse_motion_set,
+            crate::csi::evt_mouse_motion_rst,
+        ),
+        (
+            Modes::MOUSE_EXT,
+            crate::csi::evt_mouse_ext_set,
+            crate::csi::evt_mouse_ext_rst,
+        ),
+        (
+            Modes::WIN_FOCUS,
+            crate::csi::evt_win_focus_set,
+            crate::csi::evt_win_focus_rst,
+        ),
+        (
+            Modes::PASTE,
+            crate::csi::evt_paste_set,
+            crate::csi::evt_paste_rst,
+        ),
+    ];
+
+    /// Sequences turning the modes on in the order they're declared, or off in reverse.
+    pub(crate) fn csi(self, on: bool) -> String {
+        let csi = |(_, set, rst): &(Modes, Builder, Builder)| match on {
+            true => set().to_string(),
+            false => rst().to_string(),
+        };
+        let order = Modes::ORDER
+            .iter()
+            .filter(|(mode, ..)| self.contains(*mode));
+        match on {
+            true => order.map(csi).collect(),
+            false => order.rev().map(csi).collect(),
+        }
+    }
+}
+
+impl std::ops::BitOr for Modes {
+    type Output = Modes;
+    fn bitor(self, rhs: Modes) -> Modes {
+        Modes(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for Modes {
+    fn bitor_assign(&mut self, rhs: Modes) {
+        self.0 |= rhs.0;
+    }
+}
+
+static ACTIVE: AtomicU16 = AtomicU16::new(0);
+
+/// Modes turned on with [`modes_set`], or by writing their sequence with
+/// [`Csi::out`](crate::csi::Csi::out) and the like, and not turned off since.
+pub fn active_modes() -> Modes {
+    Modes(ACTIVE.load(Ordering::SeqCst))
+}
+
+// kitty keyboard flags pushed and not popped since, the latest last.
+static KBD_ENH: parking_lot::Mutex<Vec<u8>> = parking_lot::const_mutex(Vec::new());
+// length of `KBD_ENH`, for signal handlers which can't lock it.
+static KBD_ENH_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Kitty keyboard flags pushed with [`evt_kbd_enh_push`](crate::csi::evt_kbd_enh_push), as
+/// [`Csi::out`](crate::csi::Csi::out) and the like write it, and not popped since. `0` if none
+/// are.
+///
+/// [`restore`] pops as many as were pushed, and they're pushed again after the process is
+/// [suspended](suspend).
+///
+/// ```rust
+/// use etty::{KBD_ENH_DISAMBIGUATE, KBD_ENH_EVENT_TYPES};
+///
+/// etty::evt_kbd_enh_push(KBD_ENH_DISAMBIGUATE | KBD_ENH_EVENT_TYPES).out();
+/// assert_eq!(etty::guard::kbd_enh(), KBD_ENH_DISAMBIGUATE | KBD_ENH_EVENT_TYPES);
+/// etty::evt_kbd_enh_set(KBD_ENH_EVENT_TYPES, 3).out();
+/// assert_eq!(etty::guard::kbd_enh(), KBD_ENH_DISAMBIGUATE);
+/// etty::evt_kbd_enh_pop(1).out();
+/// assert_eq!(etty::guard::kbd_enh(), 0);
+/// ```
+pub fn kbd_enh() -> u8 {
+    KBD_ENH.lock().last().copied().unwrap_or(0)
+}
+
+/// Keeps track of the mode `csi` turns on or off, or of the kitty keyboard flags it pushes, pops
+/// or sets, if it does.
+pub(crate) fn track(csi: &str) {
+    if let Some(params) = csi
+        .strip_prefix("\x1b[")
+        .and_then(|csi| csi.strip_suffix('u'))
+    {
+        return track_kbd_enh(params);
+    }
+    if !csi.starts_with("\x1b[?") {
+        return;
+    }
+    for (mode, set, rst) in Modes::ORDER {
+        if set().0 == csi {
+            ACTIVE.fetch_or(mode.0, Ordering::SeqCst);
+        } else if rst().0 == csi {
+            ACTIVE.fetch_and(!mode.0, Ordering::SeqCst);
+        }
+    }
+}
+
+// `>flags`, `<n` or `=flags;mode`, the rest, e.g. the `?` of a query, is left alone.
+fn track_kbd_enh(params: &str) {
+    let mut stack = KBD_ENH.lock();
+    if let Some(flags) = params.strip_prefix('>') {
+        stack.push(flags.parse().unwrap_or(0));
+    } else if let Some(n) = params.strip_prefix('<') {
+        let n = n.parse().unwrap_or(1);
+        let len = stack.len().saturating_sub(n);
+        stack.truncate(len);
+    } else if let Some(params) = params.strip_prefix('=') {
+        let (flags, mode) = params.split_once(';').unwrap_or((params, "1"));
+        if let (Ok(flags), Some(top)) = (flags.parse::<u8>(), stack.last_mut()) {
+            match mode {
+                "2" => *top |= flags,
+                "3" => *top &= !flags,
+                _ => *top = flags,
+            }
+        }
+    }
+    KBD_ENH_DEPTH.store(stack.len(), Ordering::SeqCst);
+}
+
+/// Turns `modes` on, writing into `/dev/tty`, and keeps track of them.
+///
+/// Stdout is flushed first, so the modes don't overtake output which is still buffered.
+pub fn modes_set(modes: Modes) -> crate::Result<()> {
+    modes_set_on(&mut Tty::open()?, modes)
+}
+
+/// Turns `modes` off, in the reverse order of [`modes_set`].
+pub fn modes_rst(modes: Modes) -> crate::Result<()> {
+    modes_rst_on(&mut Tty::open()?, modes)
+}
+
+/// Same with [`modes_set`] but on `tty`.
+pub fn modes_set_on(tty: &mut Tty, modes: Modes) -> crate::Result<()> {
+    out_modes(tty, modes, true)?;
+    ACTIVE.fetch_or(modes.0, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Same with [`modes_rst`] but on `tty`.
+pub fn modes_rst_on(tty: &mut Tty, modes: Modes) -> crate::Result<()> {
+    out_modes(tty, modes, false)?;
+    ACTIVE.fetch_and(!modes.0, Ordering::SeqCst);
+    Ok(())
+}
+
+fn out_modes(tty: &mut Tty, modes: Modes, on: bool) -> crate::Result<()> {
+    let _ = std::io::stdout().flush();
+    tty.write_all(modes.csi(on).as_bytes())?;
+    tty.flush()?;
+    Ok(())
+}
@@ -21,16 +21,22 @@ pub mod macros {
 #[doc(hidden)]
 pub use macros::*;
 
+mod error;
+pub use error::Error;
+pub use error::Result;
+
 mod util;
 pub(crate) use crate::util::*;
 
 mod unix;
 
+mod signal;
+
 pub mod term;
 #[doc(hidden)]
 pub use term::*;
 
-mod input;
+pub mod guard;
 
 pub mod output;
 #[doc(hidden)]
@@ -40,6 +46,8 @@ pub mod evt;
 #[doc(hidden)]
 pub use evt::event_stream;
 
+pub mod query;
+
 pub mod csi;
 #[doc(hidden)]
 pub use csi::*;
@@ -48,6 +56,12 @@ pub mod sgr_const;
 #[doc(hidden)]
 pub use sgr_const::*;
 
+pub mod style;
+#[doc(hidden)]
+pub use style::Color;
+#[doc(hidden)]
+pub use style::Style;
+
 pub mod c0;
 #[doc(hidden)]
 pub use c0::C0;
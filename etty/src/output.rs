@@ -26,7 +26,13 @@ use std::io::Write;
 // impl<T> StdoutWrite for T where T: std::fmt::Display {}
 
 pub fn flush() {
-    std::io::stdout().flush().unwrap()
+    try_flush().unwrap()
+}
+
+/// Same with [`flush`] but returns the error instead of panicking.
+pub fn try_flush() -> crate::Result<()> {
+    std::io::stdout().flush()?;
+    Ok(())
 }
 
 pub fn outlock<'a>() -> std::io::StdoutLock<'a> {
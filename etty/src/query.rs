@@ -0,0 +1,364 @@
+//! Terminal queries.
+//!
+//! A query writes a report request to `/dev/tty` and waits for the [`Reply`]. While an event
+//! stream is running, the reply is picked out of the stream's input and every other event keeps
+//! flowing through the stream. Otherwise the query reads the tty itself, and hands whatever else
+//! it read over to the next event stream.
+//!
+//! The terminal has to be in [raw mode](crate::term::raw_mode), or the reply is held back until
+//! the next newline.
+//!
+//! ```rust,no_run
+//! use etty::query::Query;
+//!
+//! let _raw = etty::raw_mode();
+//! let timeout = std::time::Duration::from_millis(100);
+//! let (col, row) = etty::query::cus_pos(timeout)?;
+//! let dark = match etty::query::query(Query::BgColor, timeout) {
+//!     Ok(etty::evt::Reply::BgColor(r, g, b)) => (r as u16 + g as u16 + b as u16) < 384,
+//!     _ => true,
+//! };
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use crate::evt::EventAndRaw;
+use crate::evt::InputParser;
+use crate::evt::Reply;
+
+/// Report request, answered by the [`Reply`] variant of the same name.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Query {
+    CusPos,
+    DevAttr,
+    DevAttr2,
+    TermVer,
+    /// DEC private mode, e.g. `2004` for bracketed paste.
+    Mode(u16),
+    FgColor,
+    BgColor,
+    KbdEnh,
+}
+
+impl Query {
+    fn request(&self) -> String {
+        match self {
+            Query::CusPos => crate::csi::cus_pos_rpt().to_string(),
+            Query::DevAttr => crate::csi::dev_attr_rpt().to_string(),
+            Query::DevAttr2 => crate::csi::dev_attr2_rpt().to_string(),
+            Query::TermVer => crate::csi::term_ver_rpt().to_string(),
+            Query::Mode(mode) => crate::csi::mode_rpt(*mode).to_string(),
+            Query::FgColor => "\x1b]10;?\x1b\\".to_string(),
+            Query::BgColor => "\x1b]11;?\x1b\\".to_string(),
+            Query::KbdEnh => crate::csi::evt_kbd_enh_query().to_string(),
+        }
+    }
+
+    /// The reply `event` carries, if it answers this query.
+    ///
+    /// Goes by the raw bytes, so e.g. `\x1b[1;5R` is taken as a cursor position while one is
+    /// asked for, not as `Ctrl+F3`.
+    fn reply(&self, event: &EventAndRaw) -> Option<Reply> {
+        let reply = Reply::decode(&event.raw)?;
+        let answers = match (self, &reply) {
+            (Query::Mode(query), Reply::Mode { mode, .. }) => query == mode,
+            (Query::CusPos, Reply::CusPos { .. })
+            | (Query::DevAttr, Reply::DevAttr(_))
+            | (Query::DevAttr2, Reply::DevAttr2 { .. })
+            | (Query::TermVer, Reply::TermVer(_))
+            | (Query::FgColor, Reply::FgColor(..))
+            | (Query::BgColor, Reply::BgColor(..))
+            | (Query::KbdEnh, Reply::KbdEnh(_)) => true,
+            _ => false,
+        };
+        answers.then_some(reply)
+    }
+}
+
+/// Sends `query` and waits up to `timeout` for its reply.
+///
+/// Fails with [`TimedOut`](std::io::ErrorKind::TimedOut) if the terminal doesn't answer, which
+/// is how most terminals treat requests they don't support. Blocks the calling thread, so it must
+/// not be called from the task polling an [`AsyncEventAndRawStream`](crate::evt::AsyncEventAndRawStream).
+pub fn query(query: Query, timeout: std::time::Duration) -> std::io::Result<Reply> {
+    WAITING.fetch_add(1, Ordering::SeqCst);
+    let res = wait_reply(query, timeout);
+    WAITING.fetch_sub(1, Ordering::SeqCst);
+    res
+}
+
+fn wait_reply(query: Query, timeout: std::time::Duration) -> std::io::Result<Reply> {
+    let deadline = std::time::Instant::now() + timeout;
+    let mut router = ROUTER.lock();
+    // only one query at a time reads the tty itself, and no event stream starts meanwhile.
+    while router.reading {
+        if PAUSED.wait_until(&mut router, deadline).timed_out() {
+            return Err(timed_out());
+        }
+    }
+    if router.readers == 0 {
+        router.reading = true;
+        let mut stash = std::mem::take(&mut router.stash);
+        drop(router);
+        let res = read_reply(&mut stash, query, deadline);
+        let mut router = ROUTER.lock();
+        router.stash = stash;
+        router.reading = false;
+        PAUSED.notify_all();
+        return res;
+    }
+    let (tx, rx) = crossbeam::channel::bounded(1);
+    router.pending.push((query, tx.clone()));
+    drop(router);
+
+    let res = send(query).and_then(|()| rx.recv_deadline(deadline).map_err(|_| timed_out()));
+    ROUTER
+        .lock()
+        .pending
+        .retain(|(_, other)| !other.same_channel(&tx));
+    // the reply might have been routed right after the timeout.
+    res.or_else(|err| rx.try_recv().map_err(|_| err))
+}
+
+/// Cursor position in `(col, row)`, 1-based, same as [`cus_goto`](crate::csi::cus_goto).
+pub fn cus_pos(timeout: std::time::Duration) -> std::io::Result<(u16, u16)> {
+    match query(Query::CusPos, timeout)? {
+        Reply::CusPos { col, row } => Ok((col, row)),
+        reply => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("unexpected reply {reply:?}"),
+        )),
+    }
+}
+
+/// Whether a query is waiting for its reply, in which case event streams parse OSC and DCS
+/// strings as replies.
+pub(crate) fn waiting() -> bool {
+    WAITING.load(Ordering::SeqCst) > 0
+}
+
+fn timed_out() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::TimedOut, "no reply to the query")
+}
+
+fn send(query: Query) -> std::io::Result<()> {
+    crate::unix::get_tty_file()?.write_all(query.request().as_bytes())
+}
+
+struct Router {
+    /// Event streams currently reading the input.
+    readers: usize,
+    /// The ones among `readers` which read on a thread of their own.
+    threads: usize,
+    pending: Vec<(Query, crossbeam::channel::Sender<Reply>)>,
+    /// Input read by a query while no event stream was running.
+    stash: Vec<u8>,
+    /// A query is reading the tty itself, with `stash` taken out.
+    reading: bool,
+    /// Number of live [`Pause`]s, event streams don't read the input while it's non-zero.
+    pauses: usize,
+    /// Reader threads blocked in [`park`].
+    parked: usize,
+    /// Async event streams to wake once the input is resumed.
+    wakers: Vec<std::task::Waker>,
+}
+
+static ROUTER: parking_lot::Mutex<Router> = parking_lot::const_mutex(Router {
+    readers: 0,
+    threads: 0,
+    pending: Vec::new(),
+    stash: Vec::new(),
+    reading: false,
+    pauses: 0,
+    parked: 0,
+    wakers: Vec::new(),
+});
+
+// queries in `query`, whether routed or reading the tty themselves.
+static WAITING: AtomicUsize = AtomicUsize::new(0);
+
+// notified whenever `pauses`, `parked` or `reading` changes.
+static PAUSED: parking_lot::Condvar = parking_lot::Condvar::new();
+
+// holds a byte while paused, so reader threads polling it wake up and park.
+static PAUSE_PIPE: once_cell::sync::OnceCell<Option<(std::fs::File, std::fs::File)>> =
+    once_cell::sync::OnceCell::new();
+
+fn pause_pipe() -> Option<&'static (std::fs::File, std::fs::File)> {
+    PAUSE_PIPE
+        .get_or_init(|| {
+            let (rx, tx) = crate::unix::pipe().ok()?;
+            crate::unix::set_nonblocking(&rx).ok()?;
+            Some((rx, tx))
+        })
+        .as_ref()
+}
+
+/// Reads the tty until the reply comes in, everything else read is put into `stash` for the next
+/// event stream.
+fn read_reply(
+    stash: &mut Vec<u8>,
+    query: Query,
+    deadline: std::time::Instant,
+) -> std::io::Result<Reply> {
+    let mut tty = crate::unix::get_tty_file()?;
+    tty.write_all(query.request().as_bytes())?;
+
+    let mut parser = InputParser::new();
+    parser.expect_replies(true);
+    parser.feed(&std::mem::take(stash));
+    let mut buf = [0_u8; 1024];
+    let res = 'read: loop {
+        for res in parser.by_ref() {
+            let raw = match res {
+                Ok(event) => match query.reply(&event) {
+                    Some(reply) => break 'read Ok(reply),
+                    None => event.raw,
+                },
+                Err(err) => err.raw,
+            };
+            stash.extend(raw);
+        }
+        let left = deadline.saturating_duration_since(std::time::Instant::now());
+        if left.is_zero() {
+            break Err(timed_out());
+        }
+        match crate::unix::poll_read([tty.as_raw_fd()], Some(left)) {
+            Ok([true]) => {}
+            Ok([false]) => continue,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => break Err(err),
+        }
+        match tty.read(&mut buf) {
+            Ok(0) => break Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => parser.feed(&buf[..n]),
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => break Err(err),
+        }
+    };
+    stash.extend_from_slice(parser.pending());
+    res
+}
+
+/// Registration of an event stream reading the input, for as long as it's alive.
+pub(crate) struct Reader {
+    thread: bool,
+}
+
+impl Reader {
+    /// Also takes the input stashed by queries, which the stream should parse first.
+    ///
+    /// A reader `thread` must [`park`] once [`pause_fd`] turns readable.
+    pub(crate) fn register(thread: bool) -> (Reader, Vec<u8>) {
+        let mut router = ROUTER.lock();
+        // the stash is only complete once a query reading the tty is done.
+        while router.reading {
+            PAUSED.wait(&mut router);
+        }
+        router.readers += 1;
+        router.threads += thread as usize;
+        (Reader { thread }, std::mem::take(&mut router.stash))
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        let mut router = ROUTER.lock();
+        router.readers -= 1;
+        router.threads -= self.thread as usize;
+        PAUSED.notify_all();
+    }
+}
+
+/// Read end of the pipe which is readable while the input is paused, `-1` if it couldn't be made.
+pub(crate) fn pause_fd() -> std::os::unix::io::RawFd {
+    pause_pipe().map_or(-1, |(rx, _)| rx.as_raw_fd())
+}
+
+/// Blocks a reader thread for as long as the input is paused.
+pub(crate) fn park() {
+    let mut router = ROUTER.lock();
+    if router.pauses == 0 {
+        return;
+    }
+    router.parked += 1;
+    PAUSED.notify_all();
+    while router.pauses > 0 {
+        PAUSED.wait(&mut router);
+    }
+    router.parked -= 1;
+}
+
+/// Whether an async reader must hold off reading, in which case it's woken once resumed.
+pub(crate) fn paused(waker: &std::task::Waker) -> bool {
+    let mut router = ROUTER.lock();
+    if router.pauses > 0 {
+        router.wakers.push(waker.clone());
+    }
+    router.pauses > 0
+}
+
+/// Keeps event streams from reading the input until it's dropped.
+pub(crate) struct Pause(());
+
+impl Pause {
+    /// Waits for the reader threads to park. Fails with [`TimedOut`](std::io::ErrorKind::TimedOut)
+    /// if they haven't within `timeout`, e.g. one of them is busy suspending the process, in which
+    /// case the input isn't paused.
+    pub(crate) fn new(timeout: std::time::Duration) -> std::io::Result<Pause> {
+        let mut router = ROUTER.lock();
+        router.pauses += 1;
+        // resumes the input again if dropped early.
+        let pause = Pause(());
+        if let (1, Some((_, tx))) = (router.pauses, pause_pipe()) {
+            let _ = (&*tx).write(&[0]);
+        }
+        let deadline = std::time::Instant::now() + timeout;
+        while router.parked < router.threads {
+            if PAUSED.wait_until(&mut router, deadline).timed_out() {
+                drop(router);
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "event streams didn't stop reading the input",
+                ));
+            }
+        }
+        Ok(pause)
+    }
+}
+
+impl Drop for Pause {
+    fn drop(&mut self) {
+        let mut router = ROUTER.lock();
+        router.pauses -= 1;
+        if router.pauses > 0 {
+            return;
+        }
+        if let Some((rx, _)) = pause_pipe() {
+            let _ = (&*rx).read(&mut [0_u8; 64]);
+        }
+        PAUSED.notify_all();
+        router.wakers.drain(..).for_each(std::task::Waker::wake);
+    }
+}
+
+/// Hands `event` over to the query waiting for it, or gives it back.
+pub(crate) fn route(event: EventAndRaw) -> Option<EventAndRaw> {
+    let mut router = ROUTER.lock();
+    let Some((idx, reply)) = router
+        .pending
+        .iter()
+        .enumerate()
+        .find_map(|(idx, (query, _))| Some((idx, query.reply(&event)?)))
+    else {
+        return Some(event);
+    };
+    let (_, tx) = router.pending.remove(idx);
+    let _ = tx.send(reply);
+    None
+}
@@ -25,6 +25,9 @@ etty_macros::gen_sty_const! {
     STRKTHRU,
 }
 
+pub const STY_OVERLN_SET: u8 = 53;
+pub const STY_OVERLN_RST: u8 = 55;
+
 etty_macros::gen_clr_const! {
     30 =>
     BLK,
@@ -53,3 +56,13 @@ etty_macros::gen_clr_const! {
     BRGT_CYN,
     BRGT_WHT,
 }
+
+/// Followed by `5` and an index, or `2` and the RGB. Underline styles other than the single one are
+/// sub-parameters, see e.g. [`sty_underln_curly_set`](crate::csi::sty_underln_curly_set).
+///
+/// ```rust
+/// let sgr = etty::sgr!(etty::STY_UNDERLN_SET, etty::UNDERLN_CLR_SET, 2, 255, 0, 0).to_string();
+/// assert_eq!(sgr, "\x1b[4;58;2;255;0;0m");
+/// ```
+pub const UNDERLN_CLR_SET: u8 = 58;
+pub const UNDERLN_CLR_RST: u8 = 59;
@@ -0,0 +1,159 @@
+//! Self-pipe signal notification.
+//!
+//! The handler only writes a byte into every pipe registered for the signal, which is
+//! async-signal-safe. Readers then block, or poll, on the read end of their own pipe.
+
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::AtomicI32;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+#[cfg(any(target_os = "netbsd", target_os = "openbsd"))]
+pub(crate) use libc::__errno as errno_location;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) use libc::__errno_location as errno_location;
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+pub(crate) use libc::__error as errno_location;
+
+const SIGS: usize = 32;
+const SLOTS: usize = 8;
+
+static PIPES: [[AtomicI32; SLOTS]; SIGS] = [const { [const { AtomicI32::new(-1) }; SLOTS] }; SIGS];
+// number of handlers running, a write end is only closed once none of them may still use it.
+static HANDLING: AtomicUsize = AtomicUsize::new(0);
+// dispositions replaced by `handler`, which also serializes registering the first listener of a
+// signal against dropping the last one.
+static PREV: parking_lot::Mutex<[Option<libc::sigaction>; SIGS]> =
+    parking_lot::const_mutex([None; SIGS]);
+
+extern "C" fn handler(sig: libc::c_int) {
+    let Some(pipes) = PIPES.get(sig as usize) else {
+        return;
+    };
+    // `write` may clobber the `errno` of the code the signal interrupted.
+    let errno = unsafe { *errno_location() };
+    HANDLING.fetch_add(1, Ordering::SeqCst);
+    for fd in pipes {
+        let fd = fd.load(Ordering::SeqCst);
+        if fd >= 0 {
+            let b = sig as u8;
+            unsafe { libc::write(fd, &b as *const u8 as *const libc::c_void, 1) };
+        }
+    }
+    HANDLING.fetch_sub(1, Ordering::SeqCst);
+    unsafe { *errno_location() = errno };
+}
+
+/// Replaces the disposition of `sig` with `handler`, which must be async-signal-safe, and
+/// returns the previous one.
+pub(crate) fn set_handler(
+    sig: libc::c_int,
+    handler: extern "C" fn(libc::c_int),
+    flags: libc::c_int,
+) -> std::io::Result<libc::sigaction> {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = flags;
+        libc::sigemptyset(&mut action.sa_mask);
+        let mut prev: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(sig, &action, &mut prev).is_negative() {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(prev)
+    }
+}
+
+/// Current disposition of `sig`.
+pub(crate) fn get_handler(sig: libc::c_int) -> std::io::Result<libc::sigaction> {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(sig, std::ptr::null(), &mut action).is_negative() {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(action)
+    }
+}
+
+/// Read end of a pipe which receives a byte every time `sig` is delivered.
+pub(crate) struct SigPipe {
+    rx: std::fs::File,
+    // only held to keep the write end open while the handler may use it.
+    _tx: std::fs::File,
+    sig: libc::c_int,
+    slot: usize,
+}
+
+impl SigPipe {
+    pub(crate) fn new(sig: libc::c_int) -> std::io::Result<Self> {
+        assert!((sig as usize) < SIGS);
+        let (rx, tx) = crate::unix::pipe()?;
+        // the handler must never block on a full pipe.
+        crate::unix::set_nonblocking(&tx)?;
+        let mut prev = PREV.lock();
+        let slot = PIPES[sig as usize].iter().position(|fd| {
+            fd.compare_exchange(-1, tx.as_raw_fd(), Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        });
+        let Some(slot) = slot else {
+            return Err(std::io::Error::other("too many listeners for the signal"));
+        };
+        if prev[sig as usize].is_none() {
+            match set_handler(sig, handler, libc::SA_RESTART) {
+                Ok(action) => prev[sig as usize] = Some(action),
+                Err(err) => {
+                    PIPES[sig as usize][slot].store(-1, Ordering::SeqCst);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(Self {
+            rx,
+            _tx: tx,
+            sig,
+            slot,
+        })
+    }
+
+    /// Puts the read end into non-blocking mode, for polling it on a reactor.
+    pub(crate) fn set_nonblocking(&self) -> std::io::Result<()> {
+        crate::unix::set_nonblocking(&self.rx)
+    }
+
+    /// Waits for the signal, coalescing the ones delivered in the meantime.
+    pub(crate) fn wait(&self) -> std::io::Result<()> {
+        use std::io::Read;
+        let mut buf = [0_u8; 64];
+        match (&self.rx).read(&mut buf)? {
+            0 => Err(std::io::ErrorKind::UnexpectedEof.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl AsRawFd for SigPipe {
+    fn as_raw_fd(&self) -> RawFd {
+        self.rx.as_raw_fd()
+    }
+}
+
+impl Drop for SigPipe {
+    // `_tx` is closed after this, once no handler may still write to it.
+    fn drop(&mut self) {
+        let mut prev = PREV.lock();
+        let pipes = &PIPES[self.sig as usize];
+        pipes[self.slot].store(-1, Ordering::SeqCst);
+        // back to the previous disposition once nobody listens, e.g. so `SIGTSTP` stops the
+        // process again.
+        if pipes.iter().all(|fd| fd.load(Ordering::SeqCst) < 0) {
+            if let Some(action) = prev[self.sig as usize].take() {
+                unsafe { libc::sigaction(self.sig, &action, std::ptr::null_mut()) };
+            }
+        }
+        // a handler which loaded the fd before it was unregistered counts itself in first.
+        while HANDLING.load(Ordering::SeqCst) > 0 {
+            std::thread::yield_now();
+        }
+    }
+}
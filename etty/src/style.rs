@@ -0,0 +1,1121 @@
+//! Typed [SGR][wiki-sgr] colours and attributes.
+//!
+//! Unlike [`sgr!`](etty_macros::sgr) with [`sgr_const`](crate::sgr_const), a [`Style`] can be
+//! compared, merged and diffed before it's rendered into a single [`Csi`].
+//!
+//! ```rust
+//! use etty::style::{Attrs, Color, Style};
+//!
+//! let base = Style::new().fg(Color::WHT).bg(Color::Rgb(30, 30, 46));
+//! let err = base.merge(Style::new().fg(Color::RED).attrs(Attrs::BOLD));
+//! assert_eq!(err.csi().to_string(), "\x1b[1;31;48;2;30;30;46m");
+//! assert_eq!(base.diff(err).csi().to_string(), "\x1b[1;31m");
+//! ```
+//!
+//! Colours the terminal doesn't support can be mapped to the closest ones it does, with
+//! [`set_color_depth`]:
+//!
+//! ```rust,no_run
+//! etty::style::set_color_depth(etty::style::detect_color_depth());
+//! ```
+//!
+//! [wiki-sgr]: https://en.wikipedia.org/wiki/ANSI_escape_code#SGR_(Select_Graphic_Rendition)_parameters
+
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering;
+
+use crate::csi::Csi;
+
+/// Colour of the foreground, background or underline.
+#[derive(Debug, Default, Eq, PartialEq, Hash, Clone, Copy)]
+pub enum Color {
+    /// The terminal's own default, i.e. the colour is reset.
+    #[default]
+    Default,
+    /// One of the 16 colours of the terminal's palette, `0..=7` and their bright `8..=15`.
+    Ansi16(u8),
+    /// One of the xterm 256 colours.
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    pub const BLK: Color = Color::Ansi16(0);
+    pub const RED: Color = Color::Ansi16(1);
+    pub const GRN: Color = Color::Ansi16(2);
+    pub const YEL: Color = Color::Ansi16(3);
+    pub const BLU: Color = Color::Ansi16(4);
+    pub const MAG: Color = Color::Ansi16(5);
+    pub const CYN: Color = Color::Ansi16(6);
+    pub const WHT: Color = Color::Ansi16(7);
+    pub const BRGT_BLK: Color = Color::Ansi16(8);
+    pub const BRGT_RED: Color = Color::Ansi16(9);
+    pub const BRGT_GRN: Color = Color::Ansi16(10);
+    pub const BRGT_YEL: Color = Color::Ansi16(11);
+    pub const BRGT_BLU: Color = Color::Ansi16(12);
+    pub const BRGT_MAG: Color = Color::Ansi16(13);
+    pub const BRGT_CYN: Color = Color::Ansi16(14);
+    pub const BRGT_WHT: Color = Color::Ansi16(15);
+
+    /// The closest colour within `depth`, by perceptual distance, or [`Color::Default`] with
+    /// [`ColorDepth::Mono`].
+    ///
+    /// `Ansi16` colours are taken as xterm's default palette when it comes to distance, and only
+    /// `Indexed` colours beyond them are picked, as the first 16 are often themed.
+    ///
+    /// ```rust
+    /// use etty::style::{Color, ColorDepth};
+    ///
+    /// let color = Color::Rgb(255, 100, 0);
+    /// assert_eq!(color.downgrade(ColorDepth::Indexed), Color::Indexed(202));
+    /// assert_eq!(color.downgrade(ColorDepth::Ansi16), Color::BRGT_RED);
+    /// assert_eq!(color.downgrade(ColorDepth::Mono), Color::Default);
+    /// ```
+    pub fn downgrade(self, depth: ColorDepth) -> Color {
+        match (self, depth) {
+            (_, ColorDepth::Mono) => Color::Default,
+            (Color::Rgb(r, g, b), ColorDepth::Indexed) => {
+                Color::Indexed(closest((r, g, b), 16..=255))
+            }
+            (Color::Indexed(n), ColorDepth::Ansi16) if n < 16 => Color::Ansi16(n),
+            (Color::Indexed(n), ColorDepth::Ansi16) => Color::Ansi16(closest(xterm_rgb(n), 0..=15)),
+            (Color::Rgb(r, g, b), ColorDepth::Ansi16) => Color::Ansi16(closest((r, g, b), 0..=15)),
+            (color, _) => color,
+        }
+    }
+
+    /// Converts HSL, with the hue in degrees and the saturation and lightness within `0.0..=1.0`.
+    pub fn from_hsl(h: f32, s: f32, l: f32) -> Color {
+        let (s, l) = (s.clamp(0.0, 1.0), l.clamp(0.0, 1.0));
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        let h = h.rem_euclid(360.0) / 60.0;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u8 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = l - c / 2.0;
+        let channel = |v: f32| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+        Color::Rgb(channel(r), channel(g), channel(b))
+    }
+
+    /// RGB of the colour, with `Ansi16` and `Indexed` taken as xterm's default palette. `None`
+    /// for [`Color::Default`], which is up to the terminal.
+    pub fn to_rgb(self) -> Option<(u8, u8, u8)> {
+        match self {
+            Color::Default => None,
+            Color::Ansi16(n) => Some(xterm_rgb(n & 15)),
+            Color::Indexed(n) => Some(xterm_rgb(n)),
+            Color::Rgb(r, g, b) => Some((r, g, b)),
+        }
+    }
+
+    /// HSL of the colour, same as [`Color::from_hsl`] takes.
+    ///
+    /// ```rust
+    /// use etty::style::Color;
+    ///
+    /// let (h, s, l) = Color::Rgb(30, 30, 46).to_hsl().unwrap();
+    /// assert_eq!((h.round(), (s * 100.0).round(), (l * 100.0).round()), (240.0, 21.0, 15.0));
+    /// assert_eq!(Color::from_hsl(h, s, l), Color::Rgb(30, 30, 46));
+    /// ```
+    pub fn to_hsl(self) -> Option<(f32, f32, f32)> {
+        let (r, g, b) = self.to_rgb()?;
+        let [r, g, b] = [r, g, b].map(|v| v as f32 / 255.0);
+        let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+        let d = max - min;
+        let l = (max + min) / 2.0;
+        if d == 0.0 {
+            return Some((0.0, 0.0, l));
+        }
+        let s = d / (1.0 - (2.0 * l - 1.0).abs());
+        let h = match max {
+            max if max == r => ((g - b) / d).rem_euclid(6.0),
+            max if max == g => (b - r) / d + 2.0,
+            _ => (r - g) / d + 4.0,
+        };
+        Some((h * 60.0, s, l))
+    }
+
+    /// Index of the colour in the xterm 256 colours, the closest one for `Rgb`.
+    pub fn to_indexed(self) -> Option<u8> {
+        match self {
+            Color::Default => None,
+            Color::Ansi16(n) => Some(n & 15),
+            Color::Indexed(n) => Some(n),
+            Color::Rgb(r, g, b) => Some(closest((r, g, b), 16..=255)),
+        }
+    }
+
+    /// Mixes `t` of `other` into the colour, `0.0` being the colour and `1.0` being `other`.
+    ///
+    /// The result is `Rgb`, unless either is [`Color::Default`] in which case it's the colour as
+    /// it is.
+    ///
+    /// ```rust
+    /// use etty::style::Color;
+    ///
+    /// let color = Color::Rgb(0, 0, 0).blend(Color::Rgb(255, 100, 0), 0.5);
+    /// assert_eq!(color, Color::Rgb(128, 50, 0));
+    /// ```
+    pub fn blend(self, other: Color, t: f32) -> Color {
+        let (Some(from), Some(to)) = (self.to_rgb(), other.to_rgb()) else {
+            return self;
+        };
+        let t = t.clamp(0.0, 1.0);
+        let mix = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+        Color::Rgb(mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+    }
+
+    /// Raises the HSL lightness by `amount`, e.g. `0.1` for 10 points. `Default` is left as it is.
+    pub fn lighten(self, amount: f32) -> Color {
+        match self.to_hsl() {
+            Some((h, s, l)) => Color::from_hsl(h, s, l + amount),
+            None => self,
+        }
+    }
+
+    /// Lowers the HSL lightness by `amount`, same as [`Color::lighten`] the other way.
+    pub fn darken(self, amount: f32) -> Color {
+        self.lighten(-amount)
+    }
+
+    /// SGR sequence setting the colour as the foreground, e.g. in place of
+    /// [`fg_rgb`](crate::csi::fg_rgb), [downgraded](Color::downgrade) to [`color_depth`].
+    pub fn fg(self) -> Csi<'static> {
+        Style::new().fg(self).csi()
+    }
+
+    /// Same with [`Color::fg`] but as the background.
+    pub fn bg(self) -> Csi<'static> {
+        Style::new().bg(self).csi()
+    }
+
+    /// Same with [`Color::fg`] but as the colour of the underline.
+    pub fn underln(self) -> Csi<'static> {
+        Style::new().underln_clr(self).csi()
+    }
+
+    /// SGR parameters of the colour as `layer`, `Ansi16` beyond `15` wraps around.
+    fn params(self, layer: Layer, buf: &mut String) {
+        let (base, ext, rst) = match layer {
+            Layer::Fg => (30, 38, 39),
+            Layer::Bg => (40, 48, 49),
+            Layer::Underln => (0, 58, 59),
+        };
+        let _ = match (self, layer) {
+            (Color::Default, _) => write!(buf, "{}", rst),
+            (Color::Ansi16(n), Layer::Underln) => write!(buf, "{};5;{}", ext, n & 15),
+            (Color::Ansi16(n), _) if n & 15 < 8 => write!(buf, "{}", base + (n & 7)),
+            (Color::Ansi16(n), _) => write!(buf, "{}", base + 60 + (n & 7)),
+            (Color::Indexed(n), _) => write!(buf, "{};5;{}", ext, n),
+            (Color::Rgb(r, g, b), _) => write!(buf, "{};2;{};{};{}", ext, r, g, b),
+        };
+    }
+}
+
+/// Parses a colour out of:
+/// * `#rrggbb` or `#rgb`.
+/// * `rgb(r, g, b)`, with each channel within `0..=255` or a percentage.
+/// * `hsl(h, s%, l%)`, with the hue in degrees.
+/// * Names of CSS colours, which X11 shares mostly, ignoring case, spaces and `_`, or X11 grays
+///   from `gray0` to `gray100`.
+/// * xterm indices within `0..=255`, which are [`Color::Indexed`].
+/// * `default`, which is [`Color::Default`].
+///
+/// ```rust
+/// use etty::style::Color;
+///
+/// assert_eq!("#1e1e2e".parse(), Ok(Color::Rgb(30, 30, 46)));
+/// assert_eq!("rgb(30, 30, 46)".parse(), Ok(Color::Rgb(30, 30, 46)));
+/// assert_eq!("hsl(240, 100%, 50%)".parse(), Ok(Color::Rgb(0, 0, 255)));
+/// assert_eq!("Dark Slate Gray".parse(), Ok(Color::Rgb(47, 79, 79)));
+/// assert_eq!("236".parse(), Ok(Color::Indexed(236)));
+/// assert!("#1e1e2".parse::<Color>().is_err());
+/// ```
+impl std::str::FromStr for Color {
+    type Err = ParseColorError;
+
+    fn from_str(s: &str) -> Result<Color, ParseColorError> {
+        let s = s.trim().to_ascii_lowercase();
+        parse_color(&s).ok_or(ParseColorError(()))
+    }
+}
+
+/// Error of parsing a [`Color`] out of a string it doesn't match any format of.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ParseColorError(());
+
+impl std::fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid colour")
+    }
+}
+
+impl std::error::Error for ParseColorError {}
+
+fn parse_color(s: &str) -> Option<Color> {
+    if let Some(hex) = s.strip_prefix('#') {
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |idx: usize, len: usize| u8::from_str_radix(&hex[idx * len..][..len], 16);
+        return match hex.len() {
+            3 => {
+                let [r, g, b] = [0, 1, 2].map(|idx| channel(idx, 1).map(|v| v * 17));
+                Some(Color::Rgb(r.ok()?, g.ok()?, b.ok()?))
+            }
+            6 => Some(Color::Rgb(
+                channel(0, 2).ok()?,
+                channel(1, 2).ok()?,
+                channel(2, 2).ok()?,
+            )),
+            _ => None,
+        };
+    }
+    if let Some(args) = parse_args(s, "rgb") {
+        let channel = |arg: &str| match arg.strip_suffix('%') {
+            Some(pct) => pct_channel(pct),
+            None => arg.parse::<u8>().ok(),
+        };
+        return Some(Color::Rgb(
+            channel(args[0])?,
+            channel(args[1])?,
+            channel(args[2])?,
+        ));
+    }
+    if let Some(args) = parse_args(s, "hsl") {
+        let h = args[0]
+            .strip_suffix("deg")
+            .unwrap_or(args[0])
+            .parse::<f32>()
+            .ok()?;
+        let pct = |arg: &str| -> Option<f32> {
+            let pct = arg.strip_suffix('%').unwrap_or(arg).parse::<f32>().ok()?;
+            (0.0..=100.0).contains(&pct).then_some(pct / 100.0)
+        };
+        if !h.is_finite() {
+            return None;
+        }
+        return Some(Color::from_hsl(h, pct(args[1])?, pct(args[2])?));
+    }
+    if let Ok(n) = s.parse::<u8>() {
+        return Some(Color::Indexed(n));
+    }
+    let name = s.replace([' ', '_'], "");
+    if name == "default" {
+        return Some(Color::Default);
+    }
+    if let Some(pct) = name.strip_prefix("gray").or(name.strip_prefix("grey")) {
+        if !pct.is_empty() && pct.bytes().all(|b| b.is_ascii_digit()) {
+            let v = pct_channel(pct)?;
+            return Some(Color::Rgb(v, v, v));
+        }
+    }
+    let idx = NAMES
+        .binary_search_by_key(&name.as_str(), |(name, _)| name)
+        .ok()?;
+    let [_, r, g, b] = NAMES[idx].1.to_be_bytes();
+    Some(Color::Rgb(r, g, b))
+}
+
+/// Arguments of `name(a, b, c)`, which may be separated by spaces instead.
+fn parse_args<'a>(s: &'a str, name: &str) -> Option<[&'a str; 3]> {
+    let args = s
+        .strip_prefix(name)?
+        .trim_start()
+        .strip_prefix('(')?
+        .strip_suffix(')')?;
+    let mut args = args
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|arg| !arg.is_empty());
+    let res = [args.next()?, args.next()?, args.next()?];
+    args.next().is_none().then_some(res)
+}
+
+/// Channel out of a percentage within `0..=100`.
+fn pct_channel(pct: &str) -> Option<u8> {
+    let pct = pct.parse::<f32>().ok()?;
+    (0.0..=100.0)
+        .contains(&pct)
+        .then(|| (pct * 2.55).round() as u8)
+}
+
+#[derive(Clone, Copy)]
+enum Layer {
+    Fg,
+    Bg,
+    Underln,
+}
+
+/// Bitset of SGR attributes, besides the underline which is a [`Style::underln`] of its own.
+///
+/// ```rust
+/// use etty::style::Attrs;
+///
+/// let attrs = Attrs::BOLD | Attrs::ITALIC;
+/// assert!(attrs.contains(Attrs::BOLD));
+/// assert!(!attrs.contains(Attrs::BOLD | Attrs::DIM));
+/// ```
+#[derive(Debug, Default, Eq, PartialEq, Hash, Clone, Copy)]
+pub struct Attrs(u16);
+
+impl Attrs {
+    pub const NONE: Attrs = Attrs(0);
+    pub const BOLD: Attrs = Attrs(1);
+    pub const DIM: Attrs = Attrs(1 << 1);
+    pub const ITALIC: Attrs = Attrs(1 << 2);
+    pub const BLINK: Attrs = Attrs(1 << 3);
+    pub const INVRS: Attrs = Attrs(1 << 4);
+    pub const HIDE: Attrs = Attrs(1 << 5);
+    pub const STRKTHRU: Attrs = Attrs(1 << 6);
+    pub const OVERLN: Attrs = Attrs(1 << 7);
+
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+    pub const fn contains(self, other: Attrs) -> bool {
+        self.0 & other.0 == other.0
+    }
+    /// The attributes in `self` but not in `other`.
+    pub const fn without(self, other: Attrs) -> Attrs {
+        Attrs(self.0 & !other.0)
+    }
+
+    /// SGR parameters turning the attributes on.
+    fn params(self, buf: &mut String) {
+        for (_, n, _) in ATTRS.iter().filter(|(attr, ..)| self.contains(*attr)) {
+            sep(buf);
+            let _ = write!(buf, "{}", n);
+        }
+    }
+
+    /// SGR parameters turning the attributes off.
+    fn rst_params(self, buf: &mut String) {
+        let mut prev = 0;
+        for (_, _, n) in ATTRS.iter().filter(|(attr, ..)| self.contains(*attr)) {
+            // bold and dim share theirs.
+            if *n != prev {
+                sep(buf);
+                let _ = write!(buf, "{}", n);
+            }
+            prev = *n;
+        }
+    }
+}
+
+/// Attributes with the SGR parameters turning them on and off, in the order they're rendered.
+const ATTRS: [(Attrs, u8, u8); 8] = [
+    (Attrs::BOLD, 1, 22),
+    (Attrs::DIM, 2, 22),
+    (Attrs::ITALIC, 3, 23),
+    (Attrs::BLINK, 5, 25),
+    (Attrs::INVRS, 7, 27),
+    (Attrs::HIDE, 8, 28),
+    (Attrs::STRKTHRU, 9, 29),
+    (Attrs::OVERLN, 53, 55),
+];
+
+impl std::ops::BitOr for Attrs {
+    type Output = Attrs;
+    fn bitor(self, rhs: Attrs) -> Attrs {
+        Attrs(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for Attrs {
+    fn bitor_assign(&mut self, rhs: Attrs) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Kind of underline, the ones besides [`Underln::Sgl`] are rendered as `4:n`, which terminals
+/// without support for them either ignore or take as a single underline.
+#[derive(Debug, Default, Eq, PartialEq, Hash, Clone, Copy)]
+pub enum Underln {
+    #[default]
+    Sgl,
+    Dbl,
+    Curly,
+    Dot,
+    Dash,
+}
+
+impl Underln {
+    fn params(self, buf: &mut String) {
+        let _ = match self {
+            Underln::Sgl => write!(buf, "4"),
+            kind => write!(buf, "4:{}", kind as u8 + 1),
+        };
+    }
+}
+
+/// Colours and attributes of a run of text.
+///
+/// A colour left as `None` is left as it is by the terminal, while [`Color::Default`] resets it.
+///
+/// ```rust
+/// use etty::style::{Color, Style, Underln};
+///
+/// let diagnostic = Style::new().underln(Underln::Curly).underln_clr(Color::RED);
+/// assert_eq!(diagnostic.csi().to_string(), "\x1b[4:3;58;5;1m");
+/// ```
+#[derive(Debug, Default, Eq, PartialEq, Hash, Clone, Copy)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub underln_clr: Option<Color>,
+    pub underln: Option<Underln>,
+    pub attrs: Attrs,
+}
+
+impl Style {
+    pub const fn new() -> Style {
+        Style {
+            fg: None,
+            bg: None,
+            underln_clr: None,
+            underln: None,
+            attrs: Attrs::NONE,
+        }
+    }
+    pub const fn fg(mut self, color: Color) -> Style {
+        self.fg = Some(color);
+        self
+    }
+    pub const fn bg(mut self, color: Color) -> Style {
+        self.bg = Some(color);
+        self
+    }
+    pub const fn underln_clr(mut self, color: Color) -> Style {
+        self.underln_clr = Some(color);
+        self
+    }
+    pub const fn underln(mut self, kind: Underln) -> Style {
+        self.underln = Some(kind);
+        self
+    }
+    /// Adds `attrs` to the ones already set.
+    pub const fn attrs(mut self, attrs: Attrs) -> Style {
+        self.attrs = Attrs(self.attrs.0 | attrs.0);
+        self
+    }
+    pub const fn is_empty(&self) -> bool {
+        self.fg.is_none()
+            && self.bg.is_none()
+            && self.underln_clr.is_none()
+            && self.underln.is_none()
+            && self.attrs.is_empty()
+    }
+
+    /// `other` on top of `self`: its colours and underline replace the ones of `self`, its
+    /// attributes are added.
+    pub fn merge(self, other: Style) -> Style {
+        Style {
+            fg: other.fg.or(self.fg),
+            bg: other.bg.or(self.bg),
+            underln_clr: other.underln_clr.or(self.underln_clr),
+            underln: other.underln.or(self.underln),
+            attrs: self.attrs | other.attrs,
+        }
+    }
+
+    /// What has to be set on top of `self` to get `other`: the colours which changed, with the ones
+    /// `other` leaves unset being reset, and the underline and attributes `other` adds.
+    ///
+    /// An underline or attributes `other` drops can't be expressed as a `Style`, so they're left
+    /// out.
+    pub fn diff(self, other: Style) -> Style {
+        let clr = |from: Option<Color>, to: Option<Color>| match (from, to) {
+            (from, to) if from == to => None,
+            (Some(_), None) => Some(Color::Default),
+            (_, to) => to,
+        };
+        Style {
+            fg: clr(self.fg, other.fg),
+            bg: clr(self.bg, other.bg),
+            underln_clr: clr(self.underln_clr, other.underln_clr),
+            underln: other.underln.filter(|_| other.underln != self.underln),
+            attrs: other.attrs.without(self.attrs),
+        }
+    }
+
+    /// The style with its colours [downgraded](Color::downgrade) to `depth`, or left unset with
+    /// [`ColorDepth::Mono`]. Attributes are kept either way.
+    pub fn downgrade(self, depth: ColorDepth) -> Style {
+        let clr = |color: Option<Color>| match depth {
+            ColorDepth::Mono => None,
+            _ => color.map(|color| color.downgrade(depth)),
+        };
+        Style {
+            fg: clr(self.fg),
+            bg: clr(self.bg),
+            underln_clr: clr(self.underln_clr),
+            underln: self.underln,
+            attrs: self.attrs,
+        }
+    }
+
+    /// A single SGR sequence setting the style, which is empty if the style is.
+    ///
+    /// Colours are [downgraded](Style::downgrade) to [`color_depth`].
+    ///
+    /// ```rust
+    /// use etty::style::{Color, Style};
+    ///
+    /// let style = Style::new().fg(Color::BRGT_BLU).bg(Color::Indexed(236));
+    /// assert_eq!(style.csi().to_string(), "\x1b[94;48;5;236m");
+    /// assert_eq!(Style::new().csi().to_string(), "");
+    /// ```
+    pub fn csi(&self) -> Csi<'static> {
+        let mut buf = String::new();
+        self.downgrade(color_depth()).params(&mut buf);
+        sgr(buf)
+    }
+
+    /// The shortest SGR sequence turning `from` into `to`, which is empty if they're the same.
+    ///
+    /// Both are taken as the whole state of the terminal, i.e. a colour left as `None` is the
+    /// default one. Only what changed is set, unless resetting everything first is shorter.
+    /// Colours are [downgraded](Style::downgrade) to [`color_depth`] beforehand.
+    ///
+    /// ```rust
+    /// use etty::style::{Attrs, Color, Style};
+    ///
+    /// let from = Style::new().fg(Color::RED).attrs(Attrs::BOLD | Attrs::DIM | Attrs::ITALIC);
+    /// let to = Style::new().fg(Color::RED).attrs(Attrs::DIM | Attrs::ITALIC);
+    /// // `22` turns off dim along with bold.
+    /// assert_eq!(Style::transition(from, to).to_string(), "\x1b[22;2m");
+    /// assert_eq!(Style::transition(from, Style::new()).to_string(), "\x1b[0m");
+    /// assert_eq!(Style::transition(to, to).to_string(), "");
+    /// ```
+    pub fn transition(from: Style, to: Style) -> Csi<'static> {
+        let (from, to) = (from.downgrade(color_depth()), to.downgrade(color_depth()));
+        let mut buf = String::new();
+        let dropped = from.attrs.without(to.attrs);
+        let mut added = to.attrs.without(from.attrs);
+        dropped.rst_params(&mut buf);
+        let bold_dim = Attrs::BOLD | Attrs::DIM;
+        if dropped.0 & bold_dim.0 != 0 {
+            // `22` turns off both, the one kept has to be turned back on.
+            added |= Attrs(to.attrs.0 & bold_dim.0);
+        }
+        added.params(&mut buf);
+        if from.underln != to.underln {
+            sep(&mut buf);
+            match to.underln {
+                Some(kind) => kind.params(&mut buf),
+                None => buf.push_str("24"),
+            }
+        }
+        let layers = [
+            (from.fg, to.fg, Layer::Fg),
+            (from.bg, to.bg, Layer::Bg),
+            (from.underln_clr, to.underln_clr, Layer::Underln),
+        ];
+        for (from, to, layer) in layers {
+            let to = to.unwrap_or_default();
+            if from.unwrap_or_default() != to {
+                sep(&mut buf);
+                to.params(layer, &mut buf);
+            }
+        }
+
+        let mut rst = String::from("0");
+        let non_default = |color: Option<Color>| color.filter(|color| *color != Color::Default);
+        Style {
+            fg: non_default(to.fg),
+            bg: non_default(to.bg),
+            underln_clr: non_default(to.underln_clr),
+            underln: to.underln,
+            attrs: to.attrs,
+        }
+        .params(&mut rst);
+        match rst.len() < buf.len() {
+            true => sgr(rst),
+            false => sgr(buf),
+        }
+    }
+
+    fn params(&self, buf: &mut String) {
+        self.attrs.params(buf);
+        if let Some(kind) = self.underln {
+            sep(buf);
+            kind.params(buf);
+        }
+        let layers = [
+            (self.fg, Layer::Fg),
+            (self.bg, Layer::Bg),
+            (self.underln_clr, Layer::Underln),
+        ];
+        for (color, layer) in layers {
+            if let Some(color) = color {
+                sep(buf);
+                color.params(layer, buf);
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Style {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.csi().fmt(f)
+    }
+}
+
+fn sep(buf: &mut String) {
+    if !buf.is_empty() {
+        buf.push(';');
+    }
+}
+
+fn sgr(params: String) -> Csi<'static> {
+    match params.is_empty() {
+        true => Csi(Cow::Borrowed("")),
+        false => Csi(Cow::Owned(format!("\x1b[{}m", params))),
+    }
+}
+
+/// Colours a terminal can display, from the fewest to the most.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy)]
+pub enum ColorDepth {
+    Mono,
+    Ansi16,
+    Indexed,
+    Rgb,
+}
+
+impl ColorDepth {
+    /// Detects the depth out of environment variables, as looked up by `var`.
+    ///
+    /// In order:
+    /// * `FORCE_COLOR`: `0` or `false` for none, `2` for 256 colours, `3` for RGB, anything else
+    ///   for 16 colours.
+    /// * `NO_COLOR`: none when set and not empty.
+    /// * `COLORTERM`: RGB when `truecolor` or `24bit`.
+    /// * `TERM`: none when unset or `dumb`, otherwise the `colors` of its terminfo entry, and at
+    ///   last its name, e.g. `xterm-256color`.
+    ///
+    /// ```rust
+    /// use etty::style::ColorDepth;
+    ///
+    /// let depth = ColorDepth::detect(|var| match var {
+    ///     "TERM" => Some("xterm-256color".to_string()),
+    ///     "COLORTERM" => Some("truecolor".to_string()),
+    ///     _ => None,
+    /// });
+    /// assert_eq!(depth, ColorDepth::Rgb);
+    /// ```
+    ///
+    /// The terminfo entry can be in the legacy format of 16-bit numbers, or in the one of 32-bit
+    /// numbers, which is needed for `colors` of RGB terminals.
+    pub fn detect(var: impl Fn(&str) -> Option<String>) -> ColorDepth {
+        if let Some(force) = var("FORCE_COLOR") {
+            return match force.as_str() {
+                "0" | "false" => ColorDepth::Mono,
+                "2" => ColorDepth::Indexed,
+                "3" => ColorDepth::Rgb,
+                _ => ColorDepth::Ansi16,
+            };
+        }
+        if var("NO_COLOR").is_some_and(|no| !no.is_empty()) {
+            return ColorDepth::Mono;
+        }
+        if let Some("truecolor" | "24bit") = var("COLORTERM").as_deref() {
+            return ColorDepth::Rgb;
+        }
+        let term = match var("TERM") {
+            Some(term) if !term.is_empty() && term != "dumb" => term,
+            _ => return ColorDepth::Mono,
+        };
+        if term.ends_with("-direct") || term.contains("truecolor") {
+            return ColorDepth::Rgb;
+        }
+        if let Some(colors) = terminfo_colors(&term, &var) {
+            return match colors {
+                0x100_0000.. => ColorDepth::Rgb,
+                256.. => ColorDepth::Indexed,
+                8.. => ColorDepth::Ansi16,
+                _ => ColorDepth::Mono,
+            };
+        }
+        let ansi = [
+            "xterm", "screen", "tmux", "rxvt", "linux", "ansi", "color", "cygwin",
+        ];
+        match term {
+            term if term.contains("256") => ColorDepth::Indexed,
+            term if ansi.iter().any(|name| term.contains(name)) => ColorDepth::Ansi16,
+            _ => ColorDepth::Mono,
+        }
+    }
+}
+
+/// Same with [`ColorDepth::detect`] on the environment of the process.
+///
+/// Doesn't check whether the output is a terminal at all.
+pub fn detect_color_depth() -> ColorDepth {
+    ColorDepth::detect(|var| std::env::var(var).ok())
+}
+
+static DEPTH: AtomicU8 = AtomicU8::new(ColorDepth::Rgb as u8);
+
+/// Depth [`Style`]s are rendered with, [`ColorDepth::Rgb`] unless set otherwise, i.e. colours are
+/// left as they are.
+pub fn color_depth() -> ColorDepth {
+    match DEPTH.load(Ordering::Relaxed) {
+        0 => ColorDepth::Mono,
+        1 => ColorDepth::Ansi16,
+        2 => ColorDepth::Indexed,
+        _ => ColorDepth::Rgb,
+    }
+}
+
+/// Sets the depth [`Style`]s are rendered with, e.g. to the [detected](detect_color_depth) one.
+///
+/// It doesn't affect the functions of [`csi`](crate::csi), e.g. [`fg_rgb`](crate::csi::fg_rgb).
+pub fn set_color_depth(depth: ColorDepth) {
+    DEPTH.store(depth as u8, Ordering::Relaxed);
+}
+
+/// `colors` of the compiled terminfo entry of `term`, `0` if it has none, if there is an entry.
+fn terminfo_colors(term: &str, var: &impl Fn(&str) -> Option<String>) -> Option<u32> {
+    use std::path::PathBuf;
+
+    if term.contains('/') {
+        return None;
+    }
+    let defaults = ["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"].map(PathBuf::from);
+    let mut dirs = Vec::new();
+    dirs.extend(var("TERMINFO").map(PathBuf::from));
+    dirs.extend(var("HOME").map(|home| PathBuf::from(home).join(".terminfo")));
+    for dir in var("TERMINFO_DIRS").iter().flat_map(|dirs| dirs.split(':')) {
+        match dir.is_empty() {
+            true => dirs.extend(defaults.iter().cloned()),
+            false => dirs.push(PathBuf::from(dir)),
+        }
+    }
+    dirs.extend(defaults);
+
+    let first = term.chars().next()?;
+    // the first letter, or its hex code on e.g. macOS.
+    let subdirs = [first.to_string(), format!("{:x}", first as u32)];
+    let entry = dirs.iter().find_map(|dir| {
+        subdirs
+            .iter()
+            .find_map(|sub| std::fs::read(dir.join(sub).join(term)).ok())
+    })?;
+
+    // see term(5).
+    let short = |idx: usize| -> Option<usize> {
+        let bytes = entry.get(idx * 2..idx * 2 + 2)?;
+        usize::try_from(i16::from_le_bytes([bytes[0], bytes[1]])).ok()
+    };
+    let width = match short(0)? {
+        0o432 => 2,
+        0o1036 => 4,
+        _ => return None,
+    };
+    const COLORS: usize = 13;
+    if short(3)? <= COLORS {
+        return Some(0);
+    }
+    let mut off = 12 + short(1)? + short(2)?;
+    off += off % 2;
+    off += COLORS * width;
+    let bytes = entry.get(off..off + width)?;
+    let colors = match width {
+        2 => i16::from_le_bytes([bytes[0], bytes[1]]) as i32,
+        _ => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    };
+    Some(u32::try_from(colors).unwrap_or(0))
+}
+
+/// RGB of xterm's default palette.
+fn xterm_rgb(n: u8) -> (u8, u8, u8) {
+    const ANSI16: [(u8, u8, u8); 16] = [
+        (0, 0, 0),
+        (205, 0, 0),
+        (0, 205, 0),
+        (205, 205, 0),
+        (0, 0, 238),
+        (205, 0, 205),
+        (0, 205, 205),
+        (229, 229, 229),
+        (127, 127, 127),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (92, 92, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+    let level = |n: u8| match n {
+        0 => 0,
+        n => 55 + n * 40,
+    };
+    match n {
+        0..=15 => ANSI16[n as usize],
+        16..=231 => {
+            let n = n - 16;
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        _ => {
+            let gray = 8 + (n - 232) * 10;
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// Index in `range` of the xterm palette closest to `rgb`.
+fn closest(rgb: (u8, u8, u8), range: std::ops::RangeInclusive<u8>) -> u8 {
+    range
+        .min_by_key(|n| distance(rgb, xterm_rgb(*n)))
+        .unwrap_or_default()
+}
+
+/// Squared "redmean" distance, a cheap approximation of the perceived one.
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let rmean = (r1 as u32 + r2 as u32) / 2;
+    let (dr, dg, db) = (
+        r1.abs_diff(r2) as u32,
+        g1.abs_diff(g2) as u32,
+        b1.abs_diff(b2) as u32,
+    );
+    (((512 + rmean) * dr * dr) >> 8) + 4 * dg * dg + (((767 - rmean) * db * db) >> 8)
+}
+
+/// CSS colours, sorted by name.
+const NAMES: [(&str, u32); 148] = [
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A term(5) entry with `colors` as its only number, `None` leaves it out.
+    fn entry(magic: i16, width: usize, colors: Option<i32>) -> Vec<u8> {
+        let names = b"etty|fixture\0";
+        let bools = [1_u8, 0];
+        let count = match colors {
+            Some(_) => 14,
+            None => 2,
+        };
+        let header = [magic, names.len() as i16, bools.len() as i16, count, 0, 0];
+        let mut entry: Vec<u8> = header.iter().flat_map(|n| n.to_le_bytes()).collect();
+        entry.extend(names);
+        entry.extend(bools);
+        if entry.len() % 2 == 1 {
+            entry.push(0);
+        }
+        for idx in 0..count {
+            let n = colors.filter(|_| idx == 13).unwrap_or(-1);
+            match width {
+                2 => entry.extend((n as i16).to_le_bytes()),
+                _ => entry.extend(n.to_le_bytes()),
+            }
+        }
+        entry
+    }
+
+    /// Detects the depth of `term` with `entry` as its terminfo entry.
+    fn detect_with(term: &str, entry: Vec<u8>) -> ColorDepth {
+        let dir =
+            std::env::temp_dir().join(format!("etty-terminfo-{}-{}", std::process::id(), term));
+        std::fs::create_dir_all(dir.join(&term[..1])).unwrap();
+        std::fs::write(dir.join(&term[..1]).join(term), entry).unwrap();
+        let depth = ColorDepth::detect(|var| match var {
+            "TERM" => Some(term.to_string()),
+            "TERMINFO" => Some(dir.to_string_lossy().into_owned()),
+            _ => None,
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+        depth
+    }
+
+    #[test]
+    fn legacy_terminfo() {
+        let depth = detect_with("etty-legacy", entry(0o432, 2, Some(256)));
+        assert_eq!(depth, ColorDepth::Indexed);
+    }
+
+    #[test]
+    fn wide_terminfo() {
+        let depth = detect_with("etty-wide", entry(0o1036, 4, Some(0x100_0000)));
+        assert_eq!(depth, ColorDepth::Rgb);
+    }
+
+    #[test]
+    fn terminfo_without_colors() {
+        // even though the name looks like xterm's.
+        let depth = detect_with("etty-xterm", entry(0o432, 2, None));
+        assert_eq!(depth, ColorDepth::Mono);
+    }
+
+    #[test]
+    fn no_color() {
+        let depth = ColorDepth::detect(|var| match var {
+            "TERM" => Some("xterm-256color".to_string()),
+            "NO_COLOR" => Some("1".to_string()),
+            _ => None,
+        });
+        assert_eq!(depth, ColorDepth::Mono);
+    }
+}
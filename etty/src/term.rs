@@ -1,54 +1,413 @@
+use std::os::unix::io::AsRawFd;
+
+use crate::guard::Modes;
 use crate::unix;
 use crate::unix::Termios;
 
+/// Handle of a terminal, `/dev/tty` by default.
+///
+/// Modes, size queries and output all go through the same file descriptor, so they keep working
+/// while stdout is redirected, e.g. `mytool | less`. Any other terminal, e.g. a PTY slave, can be
+/// wrapped with [`From`].
+///
+/// ```rust,no_run
+/// use std::io::Write;
+///
+/// let mut tty = etty::Tty::open()?;
+/// let _raw = tty.raw_mode()?;
+/// let (cols, rows) = tty.size()?;
+/// write!(tty, "{}{}x{}", etty::cus_home(), cols, rows)?;
+/// # Ok::<(), etty::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Tty(std::fs::File);
+
+impl Tty {
+    /// Opens the controlling terminal, fails with [`Error::NotATty`](crate::Error::NotATty) if
+    /// there is none.
+    pub fn open() -> crate::Result<Tty> {
+        Ok(Tty(unix::get_tty_file()?))
+    }
+    pub fn try_clone(&self) -> crate::Result<Tty> {
+        Ok(Tty(self.0.try_clone()?))
+    }
+    /// Same with [`raw_mode`] but on this terminal.
+    pub fn raw_mode(&self) -> crate::Result<TermMode> {
+        let mut tm = self.term_mode()?;
+        tm.try_raw()?;
+        Ok(tm)
+    }
+    /// Same with [`term_mode`] but on this terminal.
+    pub fn term_mode(&self) -> crate::Result<TermMode> {
+        Ok(TermMode {
+            tty: Some(self.try_clone()?),
+            termios: None,
+            origin: None,
+        })
+    }
+    /// Size in `(cols, rows)`, same as [`term_size`].
+    pub fn size(&self) -> crate::Result<(u16, u16)> {
+        let size = unix::get_term_size(self.as_raw_fd())?;
+        Ok((size.col as u16, size.row as u16))
+    }
+    /// Size in pixels, same as [`term_size_px`].
+    pub fn size_px(&self) -> crate::Result<(u16, u16)> {
+        let size = unix::get_term_size(self.as_raw_fd())?;
+        Ok((size.x as u16, size.y as u16))
+    }
+}
+
+impl From<std::fs::File> for Tty {
+    fn from(file: std::fs::File) -> Self {
+        Tty(file)
+    }
+}
+
+impl From<Tty> for std::fs::File {
+    fn from(tty: Tty) -> Self {
+        tty.0
+    }
+}
+
+impl From<std::os::unix::io::OwnedFd> for Tty {
+    fn from(fd: std::os::unix::io::OwnedFd) -> Self {
+        Tty(fd.into())
+    }
+}
+
+impl AsRawFd for Tty {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl std::io::Read for Tty {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl std::io::Write for Tty {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
 pub fn raw_mode() -> TermMode {
+    try_raw_mode().unwrap()
+}
+
+/// Same with [`raw_mode`] but returns the error instead of panicking.
+pub fn try_raw_mode() -> crate::Result<TermMode> {
     let mut tm = TermMode::new();
-    tm.raw();
-    tm
+    tm.try_raw()?;
+    Ok(tm)
 }
 
 pub fn term_mode() -> TermMode {
     TermMode::new()
 }
 
-pub struct TermMode(Option<Termios>);
+/// Builder for a [`TermMode`] out of individual termios settings, on top of the current ones.
+///
+/// Only the settings which are called are changed, and all of them are restored when the
+/// `TermMode` is dropped or [reverted](TermMode::revert).
+///
+/// ```rust,no_run
+/// // cbreak: keys are read as they're typed without echo, but `Ctrl-C` still interrupts.
+/// let cbreak = etty::term_mode_builder().cbreak().init();
+///
+/// // password entry: line editing as usual, without echo.
+/// let no_echo = etty::term_mode_builder().echo(false).init();
+///
+/// // raw, but polling `read` returns after 100ms even without input.
+/// let raw = etty::term_mode_builder().raw().vmin(0).vtime(1).init();
+/// ```
+pub fn term_mode_builder() -> TermModeBuilder {
+    TermModeBuilder::default()
+}
+
+#[derive(Default)]
+pub struct TermModeBuilder {
+    raw: bool,
+    canon: Option<bool>,
+    echo: Option<bool>,
+    isig: Option<bool>,
+    opost: Option<bool>,
+    vmin: Option<u8>,
+    vtime: Option<u8>,
+}
+
+impl TermModeBuilder {
+    /// Starts from raw mode, same as [`raw_mode`]. Other settings apply on top of it.
+    pub fn raw(&mut self) -> &mut Self {
+        self.raw = true;
+        self
+    }
+    /// No line buffering nor echo, reading returns every key. Keeps signals and output processing,
+    /// also on top of [`raw`](TermModeBuilder::raw).
+    pub fn cbreak(&mut self) -> &mut Self {
+        self.canon(false)
+            .echo(false)
+            .isig(true)
+            .opost(true)
+            .vmin(1)
+            .vtime(0)
+    }
+    /// Canonical mode, i.e. input is line buffered and editable until a newline.
+    pub fn canon(&mut self, on: bool) -> &mut Self {
+        self.canon = Some(on);
+        self
+    }
+    pub fn echo(&mut self, on: bool) -> &mut Self {
+        self.echo = Some(on);
+        self
+    }
+    /// Whether `Ctrl-C`, `Ctrl-\` and `Ctrl-Z` raise `SIGINT`, `SIGQUIT` and `SIGTSTP`.
+    pub fn isig(&mut self, on: bool) -> &mut Self {
+        self.isig = Some(on);
+        self
+    }
+    /// Output post-processing, e.g. translating `\n` into `\r\n`.
+    pub fn opost(&mut self, on: bool) -> &mut Self {
+        self.opost = Some(on);
+        self
+    }
+    /// Minimum number of bytes a non-canonical `read` waits for.
+    pub fn vmin(&mut self, n: u8) -> &mut Self {
+        self.vmin = Some(n);
+        self
+    }
+    /// Timeout of a non-canonical `read` in tenths of a second.
+    ///
+    /// With a [`vmin`](TermModeBuilder::vmin) above `0` it's the time allowed between bytes once
+    /// the first one arrived, and `0` waits for `vmin` bytes indefinitely. With a `vmin` of `0` it's
+    /// the time to wait for any byte at all, and `0` returns right away.
+    pub fn vtime(&mut self, ds: u8) -> &mut Self {
+        self.vtime = Some(ds);
+        self
+    }
+    pub fn init(&mut self) -> TermMode {
+        self.try_init().unwrap()
+    }
+    /// Same with [`TermModeBuilder::init`] but returns the error instead of panicking.
+    pub fn try_init(&mut self) -> crate::Result<TermMode> {
+        let mut tm = TermMode::new();
+        tm.try_set(|termios| self.apply(termios))?;
+        Ok(tm)
+    }
+    /// Same with [`TermModeBuilder::init`] but on `tty`.
+    pub fn init_on(&mut self, tty: &Tty) -> TermMode {
+        self.try_init_on(tty).unwrap()
+    }
+    /// Same with [`TermModeBuilder::try_init`] but on `tty`.
+    pub fn try_init_on(&mut self, tty: &Tty) -> crate::Result<TermMode> {
+        let mut tm = tty.term_mode()?;
+        tm.try_set(|termios| self.apply(termios))?;
+        Ok(tm)
+    }
+
+    fn apply(&self, termios: Termios) -> Termios {
+        let mut termios = match self.raw {
+            true => termios.into_raw(),
+            false => termios,
+        };
+        if let Some(on) = self.canon {
+            termios.canon(on);
+        }
+        if let Some(on) = self.echo {
+            termios.echo(on);
+        }
+        if let Some(on) = self.isig {
+            termios.isig(on);
+        }
+        if let Some(on) = self.opost {
+            termios.opost(on);
+        }
+        if let Some(n) = self.vmin {
+            termios.vmin(n);
+        }
+        if let Some(ds) = self.vtime {
+            termios.vtime(ds);
+        }
+        termios
+    }
+}
+
+/// Terminal settings which are restored on drop, to the ones before the first change made by it.
+pub struct TermMode {
+    // `/dev/tty` is opened on the first change, unless given by `Tty::term_mode`.
+    tty: Option<Tty>,
+    termios: Option<Termios>,
+    // registration of `termios` as settings to hand the terminal back with, see `guard::suspend`.
+    origin: Option<u64>,
+}
 
 impl TermMode {
     fn new() -> Self {
-        Self(None)
+        Self {
+            tty: None,
+            termios: None,
+            origin: None,
+        }
     }
     pub fn raw(&mut self) {
-        self.0
-            .as_ref()
-            .cloned()
-            .unwrap_or_else(|| {
-                let termios = unix::get_term_attr().unwrap();
-                self.0 = Some(termios.clone());
+        self.try_raw().unwrap();
+    }
+    /// Same with [`TermMode::raw`] but returns the error instead of panicking.
+    pub fn try_raw(&mut self) -> crate::Result<()> {
+        self.try_set(Termios::into_raw)
+    }
+    fn try_set(&mut self, f: impl FnOnce(Termios) -> Termios) -> crate::Result<()> {
+        let fd = match &self.tty {
+            Some(tty) => tty.as_raw_fd(),
+            None => self.tty.insert(Tty::open()?).as_raw_fd(),
+        };
+        let termios = match self.termios.as_ref() {
+            Some(termios) => termios.clone(),
+            None => {
+                let termios = unix::get_term_attr(fd)?;
+                self.origin = Some(crate::guard::enter(&termios));
+                self.termios = Some(termios.clone());
                 termios
-            })
-            .into_raw()
-            .set_attr()
-            .unwrap();
+            }
+        };
+        f(termios).set_attr(fd)?;
+        Ok(())
     }
     pub fn revert(&mut self) {
-        if let Some(termios) = self.0.as_mut() {
-            termios.set_attr().unwrap();
+        self.try_revert().unwrap();
+    }
+    /// Same with [`TermMode::revert`] but returns the error instead of panicking.
+    pub fn try_revert(&mut self) -> crate::Result<()> {
+        if let (Some(tty), Some(termios)) = (&self.tty, &self.termios) {
+            termios.set_attr(tty.as_raw_fd())?;
         }
+        Ok(())
     }
 }
 
 impl Drop for TermMode {
     fn drop(&mut self) {
-        self.revert();
+        let _ = self.try_revert();
+        if let Some(id) = self.origin.take() {
+            crate::guard::leave(id);
+        }
+    }
+}
+
+/// Builder for a [`Session`], i.e. [`raw_mode`] along with a set of [`Modes`].
+///
+/// ```rust,no_run
+/// let session = etty::session_builder().alt_buf().cus_hide().mouse().init();
+/// // ...
+/// drop(session); // mouse reports off, cursor shown, main buffer, then out of raw mode.
+/// ```
+pub fn session_builder() -> SessionBuilder {
+    SessionBuilder::default()
+}
+
+#[derive(Default)]
+pub struct SessionBuilder {
+    modes: Modes,
+}
+
+impl SessionBuilder {
+    /// [`alt_buf_set`](crate::csi::alt_buf_set).
+    pub fn alt_buf(&mut self) -> &mut Self {
+        self.modes(Modes::ALT_BUF)
+    }
+    /// [`cus_hide`](crate::csi::cus_hide).
+    pub fn cus_hide(&mut self) -> &mut Self {
+        self.modes(Modes::CUS_HIDE)
+    }
+    /// [`evt_mouse_set`](crate::csi::evt_mouse_set).
+    pub fn mouse(&mut self) -> &mut Self {
+        self.modes(Modes::MOUSE)
+    }
+    /// [`evt_mouse_ext_set`](crate::csi::evt_mouse_ext_set).
+    pub fn mouse_ext(&mut self) -> &mut Self {
+        self.modes(Modes::MOUSE_EXT)
+    }
+    /// [`evt_win_focus_set`](crate::csi::evt_win_focus_set).
+    pub fn win_focus(&mut self) -> &mut Self {
+        self.modes(Modes::WIN_FOCUS)
+    }
+    /// Any other [`Modes`], e.g. [`Modes::PASTE`].
+    pub fn modes(&mut self, modes: Modes) -> &mut Self {
+        self.modes |= modes;
+        self
+    }
+    pub fn init(&mut self) -> Session {
+        self.try_init().unwrap()
+    }
+    /// Same with [`SessionBuilder::init`] but returns the error instead of panicking.
+    pub fn try_init(&mut self) -> crate::Result<Session> {
+        self.try_init_on(&Tty::open()?)
+    }
+    /// Same with [`SessionBuilder::init`] but on `tty`.
+    pub fn init_on(&mut self, tty: &Tty) -> Session {
+        self.try_init_on(tty).unwrap()
+    }
+    /// Same with [`SessionBuilder::try_init`] but on `tty`.
+    pub fn try_init_on(&mut self, tty: &Tty) -> crate::Result<Session> {
+        let tm = tty.raw_mode()?;
+        let mut tty = tty.try_clone()?;
+        // the ones already on belong to whoever turned them on, e.g. an outer session.
+        let modes = self.modes.without(crate::guard::active_modes());
+        crate::guard::modes_set_on(&mut tty, modes)?;
+        Ok(Session {
+            modes,
+            tty,
+            _tm: tm,
+        })
+    }
+}
+
+/// Raw mode and the [`Modes`] turned on by a [`SessionBuilder`], which are turned off in reverse
+/// order on drop.
+pub struct Session {
+    modes: Modes,
+    // the modes are written to, same as the one in raw mode.
+    tty: Tty,
+    // dropped after the modes are turned off.
+    _tm: TermMode,
+}
+
+impl Session {
+    /// The modes this session turned on, without the ones which were on already.
+    pub fn modes(&self) -> Modes {
+        self.modes
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = crate::guard::modes_rst_on(&mut self.tty, self.modes);
     }
 }
 
 pub fn term_size() -> (u16, u16) {
-    let size = unix::get_term_size().unwrap();
-    (size.col as u16, size.row as u16)
+    try_term_size().unwrap()
+}
+
+/// Same with [`term_size`] but returns the error instead of panicking.
+///
+/// ```rust
+/// let (cols, rows) = etty::try_term_size().unwrap_or((80, 24));
+/// ```
+pub fn try_term_size() -> crate::Result<(u16, u16)> {
+    Tty::open()?.size()
 }
 
 pub fn term_size_px() -> (u16, u16) {
-    let size = unix::get_term_size().unwrap();
-    (size.x as u16, size.y as u16)
+    try_term_size_px().unwrap()
+}
+
+/// Same with [`term_size_px`] but returns the error instead of panicking.
+pub fn try_term_size_px() -> crate::Result<(u16, u16)> {
+    Tty::open()?.size_px()
 }
@@ -1,13 +1,12 @@
 use libc::c_ushort;
 use libc::ioctl;
 use libc::termios;
-use libc::STDOUT_FILENO;
 use libc::TIOCGWINSZ;
 
-pub(crate) fn get_term_attr() -> std::io::Result<Termios> {
+pub(crate) fn get_term_attr(fd: std::os::unix::io::RawFd) -> std::io::Result<Termios> {
     unsafe {
         let mut termios = std::mem::zeroed();
-        let res = libc::tcgetattr(libc::STDOUT_FILENO, &mut termios);
+        let res = libc::tcgetattr(fd, &mut termios);
         if res.is_negative() {
             return Err(std::io::Error::last_os_error());
         }
@@ -26,8 +25,35 @@ impl Termios {
         self
     }
 
-    pub(crate) fn set_attr(&mut self) -> std::io::Result<()> {
-        let res = unsafe { libc::tcsetattr(libc::STDOUT_FILENO, libc::TCSANOW, &self.0) };
+    /// Canonical mode, i.e. input is line buffered and editable until a newline.
+    pub(crate) fn canon(&mut self, on: bool) {
+        set_flag(&mut self.0.c_lflag, libc::ICANON, on);
+    }
+
+    pub(crate) fn echo(&mut self, on: bool) {
+        set_flag(&mut self.0.c_lflag, libc::ECHO, on);
+    }
+
+    /// Generates `SIGINT`, `SIGQUIT` and `SIGTSTP` on `Ctrl-C`, `Ctrl-\` and `Ctrl-Z`.
+    pub(crate) fn isig(&mut self, on: bool) {
+        set_flag(&mut self.0.c_lflag, libc::ISIG, on);
+    }
+
+    /// Output post-processing, e.g. `\n` to `\r\n`.
+    pub(crate) fn opost(&mut self, on: bool) {
+        set_flag(&mut self.0.c_oflag, libc::OPOST, on);
+    }
+
+    pub(crate) fn vmin(&mut self, n: u8) {
+        self.0.c_cc[libc::VMIN] = n;
+    }
+
+    pub(crate) fn vtime(&mut self, ds: u8) {
+        self.0.c_cc[libc::VTIME] = ds;
+    }
+
+    pub(crate) fn set_attr(&self, fd: std::os::unix::io::RawFd) -> std::io::Result<()> {
+        let res = unsafe { libc::tcsetattr(fd, libc::TCSANOW, &self.0) };
         if res.is_negative() {
             return Err(std::io::Error::last_os_error());
         }
@@ -35,6 +61,13 @@ impl Termios {
     }
 }
 
+fn set_flag(flags: &mut libc::tcflag_t, flag: libc::tcflag_t, on: bool) {
+    match on {
+        true => *flags |= flag,
+        false => *flags &= !flag,
+    }
+}
+
 pub(crate) fn get_tty_file() -> std::io::Result<std::fs::File> {
     std::fs::OpenOptions::new()
         .read(true)
@@ -42,6 +75,78 @@ pub(crate) fn get_tty_file() -> std::io::Result<std::fs::File> {
         .open("/dev/tty")
 }
 
+/// Opens the terminal `fd` refers to anew, i.e. with file status flags of its own.
+pub(crate) fn reopen_tty(fd: std::os::unix::io::RawFd) -> std::io::Result<std::fs::File> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut buf = [0 as libc::c_char; 256];
+    let res = unsafe { libc::ttyname_r(fd, buf.as_mut_ptr(), buf.len()) };
+    if res != 0 {
+        return Err(std::io::Error::from_raw_os_error(res));
+    }
+    let path = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(std::ffi::OsStr::from_bytes(path.to_bytes()))
+}
+
+pub(crate) fn set_nonblocking(file: &std::fs::File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    unsafe {
+        let flags = libc::fcntl(file.as_raw_fd(), libc::F_GETFL);
+        if flags.is_negative() {
+            return Err(std::io::Error::last_os_error());
+        }
+        let res = libc::fcntl(file.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK);
+        if res.is_negative() {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Waits until any of `fds` is readable, or hung up. All `false` if `timeout` elapses first.
+///
+/// Negative fds are ignored.
+pub(crate) fn poll_read<const N: usize>(
+    fds: [std::os::unix::io::RawFd; N],
+    timeout: Option<std::time::Duration>,
+) -> std::io::Result<[bool; N]> {
+    let timeout = timeout
+        .map(|d| d.as_millis().min(libc::c_int::MAX as u128) as libc::c_int)
+        .unwrap_or(-1);
+    let mut pollfds = fds.map(|fd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    });
+    let res = unsafe { libc::poll(pollfds.as_mut_ptr(), N as libc::nfds_t, timeout) };
+    if res.is_negative() {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(pollfds.map(|pollfd| pollfd.revents != 0))
+}
+
+/// Creates a pipe, returns its read and write ends.
+pub(crate) fn pipe() -> std::io::Result<(std::fs::File, std::fs::File)> {
+    use std::os::unix::io::FromRawFd;
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) }.is_negative() {
+        return Err(std::io::Error::last_os_error());
+    }
+    for fd in fds {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    Ok(unsafe {
+        (
+            std::fs::File::from_raw_fd(fds[0]),
+            std::fs::File::from_raw_fd(fds[1]),
+        )
+    })
+}
+
 #[repr(C)]
 pub(crate) struct TermSize {
     pub(crate) row: c_ushort,
@@ -49,10 +154,10 @@ pub(crate) struct TermSize {
     pub(crate) x: c_ushort,
     pub(crate) y: c_ushort,
 }
-pub(crate) fn get_term_size() -> std::io::Result<TermSize> {
+pub(crate) fn get_term_size(fd: std::os::unix::io::RawFd) -> std::io::Result<TermSize> {
     unsafe {
         let mut term_size: TermSize = std::mem::zeroed();
-        let res = ioctl(STDOUT_FILENO, TIOCGWINSZ, &mut term_size as *mut _);
+        let res = ioctl(fd, TIOCGWINSZ, &mut term_size as *mut _);
         if res.is_negative() {
             return Err(std::io::Error::last_os_error());
         }
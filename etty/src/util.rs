@@ -7,19 +7,20 @@ where
         // + num_traits::NumCast
         + num_traits::Unsigned,
 {
-    assert!(!bytes.is_empty());
+    if bytes.is_empty() {
+        return None;
+    }
     const OFF_SET: u8 = 48;
-    let mut xten = T::one();
     let mut acc = T::zero();
-    let ten = T::from_u16(10_u16).unwrap();
+    let ten = T::from_u16(10_u16)?;
     // let ten = num::cast::<_, T>(10_u16).unwrap();
-    for i in (0..bytes.len()).rev() {
-        let b = bytes[i];
-        if !(b'0'..=b'9').contains(&b) {
+    for &b in bytes {
+        if !b.is_ascii_digit() {
             return None;
         }
-        acc += T::from_u8(b - OFF_SET).unwrap() * xten;
-        xten *= ten;
+        acc = acc
+            .checked_mul(&ten)?
+            .checked_add(&T::from_u8(b - OFF_SET)?)?;
     }
     Some(acc)
 }